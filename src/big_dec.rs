@@ -48,7 +48,8 @@ pub enum Error {
 
 	// operations
 	OpDivideByZero,
-	OpOverflow
+	OpOverflow,
+	OpFactorialDomain // negative or fractional factorial
 }
 
 impl Error {
@@ -59,11 +60,20 @@ impl Error {
 			Error::ParseFracPartOverflow => "too many decimals".to_string(),
 			Error::ParseBadChar => "bad character".to_string(),
 			Error::OpDivideByZero => "divide by zero".to_string(),
-			Error::OpOverflow => "overflow".to_string()
+			Error::OpOverflow => "overflow".to_string(),
+			Error::OpFactorialDomain => "factorial needs a non-negative integer".to_string()
 		}
 	}
 }
 
+// How to drop the digits that do not fit
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Rounding {
+	HalfUp,   // 2.5 -> 3, -2.5 -> -3
+	HalfEven, // 2.5 -> 2, 3.5 -> 4
+	Down      // toward zero
+}
+
 #[derive(Copy)]
 pub struct BigDec {
 	neg: bool,
@@ -73,6 +83,7 @@ pub struct BigDec {
 struct DivRet {
 	quotient: BigDec,
 	remainder: BigDec,
+	overflow: bool, // digits of the quotient were lost
 }
 
 impl DivRet {
@@ -168,6 +179,34 @@ impl BigDec {
 		ret
 	}
 
+	// Fails when the value has more than INT_LEN digits
+	pub fn from_i128(val: i128) -> Result<BigDec, Error> {
+		let mut ret = BigDec::zero();
+		ret.neg = val < 0;
+		// i128::MIN has no positive counterpart, its digits overflow below
+		let mut val_u = val.unsigned_abs();
+		let mut index = FRAC_LEN;
+		while val_u != 0 {
+			if index == MAX_LEN {
+				return Err(Error::OpOverflow);
+			}
+			ret.digits[index] = (val_u % 10) as u8;
+			val_u /= 10;
+			index += 1;
+		}
+		Ok(ret)
+	}
+
+	// Integer part, the fractional part is dropped. INT_LEN digits
+	// always fit.
+	pub fn to_i128(&self) -> i128 {
+		let mut ret: i128 = 0;
+		for i in (INT_START..MAX_LEN).rev() {
+			ret = ret * 10 + self.digits[i] as i128;
+		}
+		if self.neg { -ret } else { ret }
+	}
+
 	pub fn to_string(&self) -> String {
 		let mut ret = String::with_capacity(MAX_LEN + 2);
 		let (start_at, stop_at) = find_bounds(&self.digits);
@@ -208,25 +247,12 @@ impl BigDec {
 	}
 
 	// Converts the BigDec to an hexadecimal string. If it is
-	// negative, displays a limited number of 'F' leading bits.
-	pub fn to_string_hex(&self, _digit_count: i32) -> String {
+	// negative, displays a limited number of 'F' leading bits:
+	// digit_count digits, or twice that count, etc. if the value
+	// does not fit.
+	pub fn to_string_hex(&self, digit_count: i32) -> String {
 		let hex = BigDec::dec_to_hex(&self);
-
-		// Handle negative display
-		// + 1 because we want at least one f
-		let min_len = (hex.find_first_non_f() - INT_START) + 1;
-		let limit = if min_len < 4 {
-			4
-		}
-		else if min_len < 8 {
-			8
-		}
-		else if min_len < 16 {
-			16
-		}
-		else {
-			min_len
-		};
+		let limit = BigDec::hex_len(&hex, self.neg, digit_count);
 		let mut trunc = hex.clone();
 		for i in limit..INT_LEN {
 			trunc.digits[INT_START + i] = 0;
@@ -236,6 +262,77 @@ impl BigDec {
 		s
 	}
 
+	// Number of hexadecimal digits to display
+	fn hex_len(hex: &BigDec, neg: bool, digit_count: i32) -> usize {
+		let min_len = if neg {
+			// Handle negative display
+			// + 1 because we want at least one f
+			(hex.find_first_non_f() - INT_START) + 1
+		}
+		else {
+			std::cmp::max(hex.int_digit_count(), 1)
+		};
+		let mut limit = if digit_count < 1 { 1 } else { digit_count as usize };
+		while limit < min_len {
+			limit *= 2;
+		}
+		if limit > INT_LEN {
+			limit = std::cmp::min(min_len, INT_LEN);
+		}
+		limit
+	}
+
+	// Bits of the integer part, least significant first, with the same
+	// word size rules as to_string_hex(). Negative values use the two's
+	// complement.
+	pub fn to_bits(&self, digit_count: i32) -> Vec<bool> {
+		let hex = BigDec::dec_to_hex(&self);
+		let limit = BigDec::hex_len(&hex, self.neg, digit_count);
+		let mut ret = Vec::with_capacity(limit * 4);
+		for i in 0..limit {
+			let nibble = hex.digits[INT_START + i];
+			for b in 0..4 {
+				ret.push(nibble & (1 << b) != 0);
+			}
+		}
+		ret
+	}
+
+	// Binary literal of the integer part. Like in to_string_hex(),
+	// negative values use two's complement on digit_count nibbles.
+	pub fn to_string_bin(&self, digit_count: i32) -> String {
+		let bits = self.to_bits(digit_count);
+		let mut s: String = bits.iter().rev()
+			.map(|&bit| if bit { '1' } else { '0' })
+			.collect();
+		if !self.neg {
+			let zero_count = s.len() - std::cmp::max(s.trim_start_matches('0').len(), 1);
+			s.drain(..zero_count);
+		}
+		s.insert_str(0, "0b");
+		s
+	}
+
+	// Hexadecimal literal from bits given least significant first,
+	// with all the digits so that the word size is visible
+	pub fn bits_to_hex(bits: &[bool]) -> String {
+		let mut ret = String::from("0x");
+		for nibble_bits in bits.chunks(4).rev() {
+			let mut nibble = 0;
+			for (b, &bit) in nibble_bits.iter().enumerate() {
+				if bit {
+					nibble |= 1 << b;
+				}
+			}
+			ret.push(char_from_digit(nibble));
+		}
+		ret
+	}
+
+	pub fn is_integer(&self) -> bool {
+		self.digits[0..FRAC_LEN].iter().all(|&d| d == 0)
+	}
+
 	// Converts a BigDec which works with decimal digits into
 	// a BigDec with hexadecimal digits. To be used for display
 	// only.
@@ -275,6 +372,54 @@ impl BigDec {
 		res
 	}
 
+	// Number of digits of the integer part, 0 for 0.xxx values
+	pub fn int_digit_count(&self) -> usize {
+		let mut count = INT_LEN;
+		while count > 0 && self.digits[INT_START + count - 1] == 0 {
+			count -= 1;
+		}
+		count
+	}
+
+	// Keeps frac_len fractional digits at most
+	pub fn round(nv: BigDec, frac_len: usize, rounding: Rounding) -> Result<BigDec, Error> {
+		if frac_len >= FRAC_LEN {
+			return Ok(nv);
+		}
+		let cut = FRAC_LEN - frac_len; // index of the last digit kept
+		let first_dropped = nv.digits[cut - 1];
+		let rest_is_zero = nv.digits[0..cut - 1].iter().all(|&d| d == 0);
+		let round_up = match rounding {
+			Rounding::HalfUp => first_dropped >= 5,
+			Rounding::HalfEven => {
+				if first_dropped != 5 {
+					first_dropped > 5
+				}
+				else if !rest_is_zero {
+					true
+				}
+				else {
+					nv.digits[cut] % 2 == 1
+				}
+			},
+			Rounding::Down => false
+		};
+
+		let mut ret = nv;
+		for i in 0..cut {
+			ret.digits[i] = 0;
+		}
+		if round_up {
+			let mut ulp = [0u8;MAX_LEN];
+			ulp[cut] = 1;
+			try!(BigDec::accumulate_u(&ulp, &mut ret.digits));
+		}
+		if ret.is_zero() {
+			ret.neg = false;
+		}
+		Ok(ret)
+	}
+
 	pub fn max_len() -> usize {
 		MAX_LEN
 	}
@@ -288,6 +433,11 @@ impl BigDec {
 		true
 	}
 
+	// Less than zero, -0 is not negative
+	pub fn is_negative(&self) -> bool {
+		self.neg && !self.is_zero()
+	}
+
 	pub fn negate(&self) -> BigDec {
 		let mut ret = *self;
 		ret.neg = !ret.neg;
@@ -378,6 +528,19 @@ impl BigDec {
 		return 0;
 	}
 
+	// Same as compare(), taking the signs into account
+	pub fn compare_signed(nv0: BigDec, nv1: BigDec) -> i32 {
+		if nv0.is_zero() && nv1.is_zero() {
+			return 0; // -0 is 0
+		}
+		match (nv0.neg, nv1.neg) {
+			(false, false) => BigDec::compare(nv0, nv1),
+			(true, true) => BigDec::compare(nv1, nv0),
+			(false, true) => 1,
+			(true, false) => -1
+		}
+	}
+
 	pub fn sub(nv0: BigDec, nv1: BigDec) -> Result<BigDec, Error> {
 		if nv0.neg && !nv1.neg {
 			let mut ret = match BigDec::add_u(nv0, nv1) {
@@ -471,6 +634,7 @@ impl BigDec {
 		let stop_at = if with_frac { MAX_LEN+FRAC_LEN } else { MAX_LEN };
 
 		let mut result = BigDec::zero();
+		let mut overflow = false;
 
 		loop {
 			src_digit_index = match src_digit_index {
@@ -498,6 +662,7 @@ impl BigDec {
 				tmp = next_diff;
 				counter += 1;
 			}
+			overflow = overflow || result.digits[MAX_LEN-1] != 0;
 			result.shift_right();
 			result.digits[target_index] = counter;
 
@@ -514,7 +679,7 @@ impl BigDec {
 			tmp.shift_right();
 		}
 		//println!("u_div: out: {:?} ~ {:?}", result, tmp);
-		DivRet { quotient: result, remainder: tmp }
+		DivRet { quotient: result, remainder: tmp, overflow }
 	}
 
 	pub fn div(nv0: BigDec, nv1: BigDec) -> Result<BigDec, Error> {
@@ -522,28 +687,12 @@ impl BigDec {
 			return Err(Error::OpDivideByZero);
 		}
 
-		if nv0.neg {
-			if nv1.neg {
-				let div_ret = BigDec::div_u(nv0, nv1, true);
-				return Ok(div_ret.quotient);
-			}
-			else {
-				let mut div_ret = BigDec::div_u(nv0, nv1, true);
-				div_ret.quotient.neg = true;
-				return Ok(div_ret.quotient);
-			}
-		}
-		else {
-			if nv1.neg {
-				let mut div_ret = BigDec::div_u(nv0, nv1, true);
-				div_ret.quotient.neg = true;
-				return Ok(div_ret.quotient);
-			}
-			else {
-				let div_ret = BigDec::div_u(nv0, nv1, true);
-				return Ok(div_ret.quotient);
-			}
+		let mut div_ret = BigDec::div_u(nv0, nv1, true);
+		if div_ret.overflow {
+			return Err(Error::OpOverflow);
 		}
+		div_ret.quotient.neg = nv0.neg != nv1.neg;
+		Ok(div_ret.quotient)
 	}
 
 	pub fn div_mod(nv0: BigDec, nv1: BigDec) -> Result<BigDec, Error> {
@@ -566,13 +715,12 @@ impl BigDec {
 		if n.is_zero() {
 			return Ok(BigDec::from_i32(1));
 		}
-		// Clear fractional part
-		for i in 0..FRAC_LEN {
-			n.digits[i] = 0;
+		if n.neg || !n.is_integer() {
+			return Err(Error::OpFactorialDomain);
+		}
+		if BigDec::fact_digits_estimate(n) > INT_LEN as f64 {
+			return Err(Error::OpOverflow);
 		}
-		// Clear sign
-		let sign = n.neg;
-		n.neg = false;
 		let one = BigDec::from_i32(1);
 		let mut val = n;
 		loop {
@@ -587,10 +735,18 @@ impl BigDec {
 			val = mul_res.unwrap();
 			n = n_minus_one;
 		}
-		val.neg = sign;
 		Ok(val)
 	}
 
+	// Approximate number of digits of n!, from Stirling's formula which
+	// is a little below the real value. Avoids multiplying for nothing
+	// when the result cannot fit.
+	fn fact_digits_estimate(n: BigDec) -> f64 {
+		let n = n.to_i128() as f64;
+		let ln_fact = n * n.ln() - n + 0.5 * (2.0 * std::f64::consts::PI * n).ln();
+		ln_fact / std::f64::consts::LN_10
+	}
+
 	pub fn and(left: BigDec, right: BigDec) -> Result<BigDec, Error> {
 		let left_hex = left.dec_to_hex();
 		let right_hex = right.dec_to_hex();
@@ -599,7 +755,7 @@ impl BigDec {
 		for i in INT_START..MAX_LEN {
 			res.digits[i] = left_hex.digits[i] & right_hex.digits[i];
 		}
-		Ok(res)
+		res.hex_to_dec()
 	}
 
 	// Converts a BigDec which internal representation uses a base 16
@@ -635,6 +791,15 @@ impl BigDec {
 	// The digits can be separated with an underscore
 	// ex: 14_950.234_845
 	pub fn parse_chars(input_chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<BigDec, Error> {
+		BigDec::parse_chars_sep(input_chars, '.', '_')
+	}
+
+	// Same as parse_chars(), but decimal numbers may also use the given
+	// decimal and group separators, as written in some locales. They are
+	// only accepted when followed by a digit. When '.' is the group
+	// separator, like in 1.234,5, it is never the decimal point.
+	pub fn parse_chars_sep<I: Iterator<Item = char> + Clone>(input_chars: &mut std::iter::Peekable<I>,
+		decimal: char, group: char) -> Result<BigDec, Error> {
 		let c = {
 			let c_opt = input_chars.peek();
 			if c_opt.is_none() {
@@ -671,6 +836,17 @@ impl BigDec {
 				let c = c_opt.unwrap();
 				*c
 			};
+			let c = if radix == 10 && ((c == decimal && c != '.') || (c == group && c != '_')) {
+				let mut ahead = input_chars.clone();
+				ahead.next();
+				match ahead.peek() {
+					Some(next) if next.is_digit(10) => if c == decimal { '.' } else { '_' },
+					_ => break
+				}
+			}
+			else {
+				c
+			};
 
 			if c == 'x' {
 				if !radix_found && shift_count == 1 && first_digit32 == 0 {
@@ -679,17 +855,19 @@ impl BigDec {
 					radix_found = true;
 				}
 				else {
-				    return Err(Error::ParseBadChar);
+					// A name after the number, like in 2x
+					break;
 				}
 			}
-			else if c == 'b' {
+			else if c == 'b' && radix != 16 {
+				// In hexadecimal this is a digit
 				if !radix_found && shift_count == 1 && first_digit32 == 0 {
 					// Binary
 					radix = 2;
 					radix_found = true;
 				}
 				else {
-				    return Err(Error::ParseBadChar);
+					break;
 				}
 			}
 			else if c == '.' {
@@ -858,6 +1036,9 @@ fn test_div() {
 	let mut one_point_six = BigDec::from_i32(16);
 	one_point_six.shift_left();
 	assert_eq!("1.25", BigDec::div(BigDec::from_i32(2), one_point_six).unwrap().to_string());
+	let tiny = BigDec::parse_str("0.00000000000000000001").unwrap();
+	assert!(match BigDec::div(BigDec::from_i32(-1), tiny) { Err(Error::OpOverflow) => true, _ => false });
+	assert_eq!("50000000000000000000", BigDec::div(BigDec::parse_str("0.5").unwrap(), tiny).unwrap().to_string());
 }
 
 #[test]
@@ -868,10 +1049,31 @@ fn test_div_mod() {
 	assert_eq!("-4",  BigDec::div_mod(BigDec::from_i32(-100),  BigDec::from_i32(-48)).unwrap().to_string());
 }
 
+#[test]
+fn test_compare_signed() {
+	let cmp = |a: i32, b: i32| BigDec::compare_signed(BigDec::from_i32(a), BigDec::from_i32(b));
+	assert_eq!(-1, cmp(2, 3));
+	assert_eq!(1, cmp(-2, -3));
+	assert_eq!(-1, cmp(-5, 1));
+	assert_eq!(1, cmp(0, -1));
+	assert_eq!(0, cmp(-7, -7));
+	assert_eq!(0, BigDec::compare_signed(BigDec::zero(), BigDec::zero().negate()));
+	assert_eq!("4", BigDec::and(BigDec::from_i32(12), BigDec::from_i32(6)).unwrap().to_string());
+}
+
 #[test]
 fn test_fact() {
 	assert_eq!("120",  BigDec::fact(BigDec::from_i32(5)).unwrap().to_string());
-	assert_eq!("-120",  BigDec::fact(BigDec::from_i32(-5)).unwrap().to_string());
+	assert_eq!("1",  BigDec::fact(BigDec::zero()).unwrap().to_string());
+	assert_eq!("51090942171709440000",  BigDec::fact(BigDec::from_i32(21)).unwrap().to_string());
+	assert!(match BigDec::fact(BigDec::from_i32(-5)) { Err(Error::OpFactorialDomain) => true, _ => false });
+	let fractional = BigDec::parse_str("2.5").unwrap();
+	assert!(match BigDec::fact(fractional) { Err(Error::OpFactorialDomain) => true, _ => false });
+	assert!(match BigDec::fact(BigDec::from_i32(22)) { Err(Error::OpOverflow) => true, _ => false });
+	let huge = BigDec::parse_str("99999999999999999999").unwrap();
+	assert!(match BigDec::fact(huge) { Err(Error::OpOverflow) => true, _ => false });
+	assert!(match BigDec::from_i128(std::i128::MIN) { Err(Error::OpOverflow) => true, _ => false });
+	assert_eq!("-99999999999999999999", BigDec::from_i128(-99999999999999999999).unwrap().to_string());
 }
 
 #[test]
@@ -912,11 +1114,35 @@ fn test_parse_underscore() {
 	assert_eq!("1234567", nv2.unwrap().to_string());
 }
 
+#[test]
+fn test_parse_separators() {
+	let parse = |text: &str| {
+		let mut ic = text.chars().peekable();
+		let nv = BigDec::parse_chars_sep(&mut ic, ',', '\'');
+		(nv.unwrap().to_string(), ic.collect::<String>())
+	};
+	assert_eq!(("1234.5".to_string(), "".to_string()), parse("1'234,5"));
+	assert_eq!(("1.25".to_string(), "".to_string()), parse("1.25"));
+	assert_eq!(("12".to_string(), ",".to_string()), parse("12,"));
+	assert_eq!(("12".to_string(), "' ".to_string()), parse("12' "));
+	assert_eq!(("15".to_string(), ",5".to_string()), parse("0xF,5"));
+
+	let parse_european = |text: &str| {
+		let mut ic = text.chars().peekable();
+		BigDec::parse_chars_sep(&mut ic, ',', '.').unwrap().to_string()
+	};
+	assert_eq!("1234567.5", parse_european("1.234.567,5"));
+	assert_eq!("1234.5", parse_european("1.234,5"));
+	assert_eq!("1234", parse_european("1.234"));
+	assert_eq!("15", parse_european("1.5"));
+}
+
 #[test]
 fn test_parse_hex() {
 	let nv = BigDec::parse_str("0xffff");
 	assert!(nv.is_ok());
 	assert_eq!("65535", nv.unwrap().to_string());
+	assert_eq!("2748", BigDec::parse_str("0xabc").unwrap().to_string());
 }
 
 #[test]
@@ -933,3 +1159,65 @@ fn test_parse_bin() {
 	assert!(nv.is_ok());
 	assert_eq!("240", nv.unwrap().to_string());
 }
+
+#[test]
+fn test_round() {
+	let round = |s: &str, frac_len: usize, rounding: Rounding| {
+		BigDec::round(BigDec::parse_str(s).unwrap(), frac_len, rounding).unwrap().to_string()
+	};
+	assert_eq!("1.24", round("1.235", 2, Rounding::HalfUp));
+	assert_eq!("1.24", round("1.235", 2, Rounding::HalfEven));
+	assert_eq!("1.22", round("1.225", 2, Rounding::HalfEven));
+	assert_eq!("1.23", round("1.2251", 2, Rounding::HalfEven));
+	assert_eq!("1.23", round("1.239", 2, Rounding::Down));
+	assert_eq!("10", round("9.99", 1, Rounding::HalfUp));
+	assert_eq!("-3", BigDec::round(BigDec::parse_str("2.5").unwrap().negate(),
+		0, Rounding::HalfUp).unwrap().to_string());
+	assert_eq!("0", BigDec::round(BigDec::parse_str("0.001").unwrap().negate(),
+		2, Rounding::HalfUp).unwrap().to_string());
+}
+
+#[test]
+fn test_i128() {
+	let max = BigDec::parse_str("99999999999999999999").unwrap();
+	assert_eq!(99999999999999999999, max.to_i128());
+	assert_eq!(-12, BigDec::parse_str("12.9").unwrap().negate().to_i128());
+	assert_eq!("-1234", BigDec::from_i128(-1234).unwrap().to_string());
+	assert_eq!(max, BigDec::from_i128(99999999999999999999).unwrap());
+	assert!(BigDec::from_i128(100000000000000000000).is_err());
+}
+
+#[test]
+fn test_int_digit_count() {
+	assert_eq!(0, BigDec::parse_str("0.5").unwrap().int_digit_count());
+	assert_eq!(4, BigDec::parse_str("1234.5").unwrap().int_digit_count());
+}
+
+#[test]
+fn test_to_string_hex() {
+	assert_eq!("0xFF", BigDec::from_i32(-1).to_string_hex(2));
+	assert_eq!("0xFF38", BigDec::from_i32(-200).to_string_hex(2));
+	assert_eq!("0xFFFFFFFF", BigDec::from_i32(-1).to_string_hex(8));
+	assert_eq!("0x1F", BigDec::from_i32(31).to_string_hex(8));
+}
+
+#[test]
+fn test_bits() {
+	let bits = BigDec::from_i32(5).to_bits(2);
+	assert_eq!(8, bits.len());
+	assert_eq!(vec![true, false, true, false, false, false, false, false], bits);
+	assert_eq!("0x05", BigDec::bits_to_hex(&bits));
+
+	let bits = BigDec::from_i32(-2).to_bits(4);
+	assert_eq!(16, bits.len());
+	assert!(!bits[0]);
+	assert!(bits[1..].iter().all(|&b| b));
+	assert_eq!("0xFFFE", BigDec::bits_to_hex(&bits));
+
+	assert_eq!(16, BigDec::from_i32(300).to_bits(2).len());
+	assert!(BigDec::zero().negate().to_bits(2).iter().all(|&b| !b));
+
+	assert_eq!("0b101", BigDec::from_i32(5).to_string_bin(2));
+	assert_eq!("0b0", BigDec::zero().to_string_bin(2));
+	assert_eq!("0b11111110", BigDec::from_i32(-2).to_string_bin(2));
+}
@@ -0,0 +1,106 @@
+use gtk;
+use gtk::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use big_dec::BigDec;
+
+const BITS_PER_LINE: usize = 16;
+const MAX_BITS: usize = 64;
+
+// Grid of the bits of the result. Clicking a bit flips it and
+// replaces the input with the new value written in hexadecimal.
+pub struct BitPanel {
+	pub grid: gtk::Grid,
+	entry: gtk::Entry,
+	buttons: RefCell<Vec<gtk::Button>>, // one per bit, least significant first
+	bits: RefCell<Vec<bool>>
+}
+
+impl BitPanel {
+	pub fn new(entry: &gtk::Entry) -> Rc<BitPanel> {
+		let grid = gtk::Grid::new();
+		grid.set_column_homogeneous(true);
+		grid.set_no_show_all(true);
+		Rc::new(BitPanel {
+			grid,
+			entry: entry.clone(),
+			buttons: RefCell::new(Vec::new()),
+			bits: RefCell::new(Vec::new())
+		})
+	}
+
+	// Creates the bit buttons, most significant first, with the bit
+	// index above each one
+	fn rebuild(panel: &Rc<BitPanel>, bit_count: usize) {
+		for child in panel.grid.get_children() {
+			panel.grid.remove(&child);
+		}
+
+		let mut buttons = Vec::with_capacity(bit_count);
+		for index in 0..bit_count {
+			let button = gtk::Button::new_with_label("0");
+			gtk::WidgetExt::set_name(&button, "bit");
+			button.set_relief(gtk::ReliefStyle::None);
+			button.connect_clicked({
+				let panel = panel.clone();
+				move |_| { panel.flip(index); }
+			});
+			buttons.push(button);
+		}
+
+		let line_count = (bit_count + BITS_PER_LINE - 1) / BITS_PER_LINE;
+		for index in 0..bit_count {
+			let line = (line_count - 1 - index / BITS_PER_LINE) as i32;
+			let column = (BITS_PER_LINE - 1 - index % BITS_PER_LINE) as i32;
+			let index_label = gtk::Label::new(Some(index.to_string().as_str()));
+			gtk::WidgetExt::set_name(&index_label, "bit_index");
+			panel.grid.attach(&index_label, column, line * 2, 1, 1);
+			panel.grid.attach(&buttons[index], column, line * 2 + 1, 1, 1);
+		}
+		for child in panel.grid.get_children() {
+			child.show();
+		}
+		*panel.buttons.borrow_mut() = buttons;
+	}
+
+	fn flip(&self, index: usize) {
+		let hex = {
+			let mut bits = self.bits.borrow_mut();
+			if index >= bits.len() {
+				return;
+			}
+			bits[index] = !bits[index];
+			BigDec::bits_to_hex(&bits)
+		};
+		// Changing the text evaluates it again, which updates the panel
+		self.entry.set_text(&hex);
+		self.entry.set_position(-1);
+	}
+
+	// Shows the bits of the value, using at least word_size bits.
+	// Values that are not integers or do not fit have no bits to show.
+	pub fn update(panel: &Rc<BitPanel>, value: Option<BigDec>, word_size: u32, visible: bool) {
+		panel.grid.set_visible(visible);
+		if !visible {
+			return;
+		}
+
+		let bits = match value {
+			Some(nv) if nv.is_integer() => nv.to_bits((word_size / 4) as i32),
+			_ => Vec::new()
+		};
+		let fits = !bits.is_empty() && bits.len() <= MAX_BITS;
+		panel.grid.set_sensitive(fits);
+		if !fits {
+			return;
+		}
+
+		if bits.len() != panel.buttons.borrow().len() {
+			BitPanel::rebuild(panel, bits.len());
+		}
+		for (button, &bit) in panel.buttons.borrow().iter().zip(bits.iter()) {
+			button.set_label(if bit { "1" } else { "0" });
+		}
+		*panel.bits.borrow_mut() = bits;
+	}
+}
@@ -0,0 +1,218 @@
+use std;
+use big_dec;
+use big_dec::BigDec;
+use funcs::Function;
+use settings::Settings;
+
+// Parameters of a CRC, named like in the catalogue of parametrised CRC
+// algorithms. The register is reflected when refin is set.
+struct Crc {
+	width: u32,
+	poly: u64,
+	init: u64,
+	refin: bool,
+	xorout: u64
+}
+
+const CRC8: Crc = Crc { width: 8, poly: 0x07, init: 0, refin: false, xorout: 0 };
+const CRC16_ARC: Crc = Crc { width: 16, poly: 0x8005, init: 0, refin: true, xorout: 0 };
+const CRC16_CCITT: Crc = Crc { width: 16, poly: 0x1021, init: 0xffff, refin: false, xorout: 0 };
+const CRC16_MODBUS: Crc = Crc { width: 16, poly: 0x8005, init: 0xffff, refin: true, xorout: 0 };
+const CRC32: Crc = Crc { width: 32, poly: 0x04c11db7, init: 0xffffffff, refin: true, xorout: 0xffffffff };
+
+impl Crc {
+	// Bit by bit, inputs are short
+	fn compute(&self, bytes: &[u8]) -> u64 {
+		let mask = std::u64::MAX >> (64 - self.width);
+		if self.refin {
+			let poly = self.poly.reverse_bits() >> (64 - self.width);
+			let mut reg = self.init;
+			for &byte in bytes {
+				reg ^= byte as u64;
+				for _ in 0..8 {
+					reg = if reg & 1 != 0 { (reg >> 1) ^ poly } else { reg >> 1 };
+				}
+			}
+			return (reg ^ self.xorout) & mask;
+		}
+		let top = 1 << (self.width - 1);
+		let mut reg = self.init;
+		for &byte in bytes {
+			reg ^= (byte as u64) << (self.width - 8);
+			for _ in 0..8 {
+				reg = if reg & top != 0 { (reg << 1) ^ self.poly } else { reg << 1 } & mask;
+			}
+		}
+		(reg ^ self.xorout) & mask
+	}
+}
+
+fn crc8(bytes: &[u8]) -> u64 {
+	CRC8.compute(bytes)
+}
+
+fn crc16(bytes: &[u8]) -> u64 {
+	CRC16_ARC.compute(bytes)
+}
+
+fn crc16ccitt(bytes: &[u8]) -> u64 {
+	CRC16_CCITT.compute(bytes)
+}
+
+fn crc16modbus(bytes: &[u8]) -> u64 {
+	CRC16_MODBUS.compute(bytes)
+}
+
+fn crc32(bytes: &[u8]) -> u64 {
+	CRC32.compute(bytes)
+}
+
+fn adler32(bytes: &[u8]) -> u64 {
+	let (mut a, mut b) = (1u64, 0u64);
+	for &byte in bytes {
+		a = (a + byte as u64) % 65521;
+		b = (b + a) % 65521;
+	}
+	b << 16 | a
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+	let mut hash: u32 = 0x811c9dc5;
+	for &byte in bytes {
+		hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+	}
+	hash as u64
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for &byte in bytes {
+		hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+// Checksum of the bytes of an integer or of a string literal, such as
+// crc32(0x1234, 2) or crc32("123456789")
+#[derive(Clone)]
+pub struct Checksum {
+	name: &'static str,
+	help: &'static str,
+	compute: fn(bytes: &[u8]) -> u64
+}
+
+pub const CHECKSUMS: [Checksum; 8] = [
+	Checksum { name: "crc8", help: "crc8(x, bytes, le): CRC-8 of the bytes of x or of a string",
+		compute: crc8 },
+	Checksum { name: "crc16", help: "crc16(x, bytes, le): CRC-16/ARC of the bytes of x or of a string",
+		compute: crc16 },
+	Checksum { name: "crc16ccitt", help: "crc16ccitt(x, bytes, le): CRC-16/CCITT-FALSE of the bytes of x or of a string",
+		compute: crc16ccitt },
+	Checksum { name: "crc16modbus", help: "crc16modbus(x, bytes, le): CRC-16/MODBUS of the bytes of x or of a string",
+		compute: crc16modbus },
+	Checksum { name: "crc32", help: "crc32(x, bytes, le): CRC-32 of the bytes of x or of a string",
+		compute: crc32 },
+	Checksum { name: "adler32", help: "adler32(x, bytes, le): Adler-32 of the bytes of x or of a string",
+		compute: adler32 },
+	Checksum { name: "fnv1a", help: "fnv1a(x, bytes, le): 32-bit FNV-1a hash of the bytes of x or of a string",
+		compute: fnv1a },
+	Checksum { name: "fnv1a64", help: "fnv1a64(x, bytes, le): 64-bit FNV-1a hash of the bytes of x or of a string",
+		compute: fnv1a64 },
+];
+
+// Fewest bytes holding val, which fits in 64 bits
+fn byte_count(val: i128) -> usize {
+	let mut ret = 1;
+	while ret < 8 && (val >= 1 << (ret * 8) || val < -(1 << (ret * 8 - 1))) {
+		ret += 1;
+	}
+	ret
+}
+
+fn from_u64(val: u64) -> Result<BigDec, big_dec::Error> {
+	BigDec::from_i128(val as i128)
+}
+
+impl Function for Checksum {
+	fn name(&self) -> &str {
+		self.name
+	}
+
+	// x, then the number of bytes taken from x, then 1 for little endian
+	fn arity(&self) -> (usize, usize) {
+		(1, 3)
+	}
+
+	fn help(&self) -> &str {
+		self.help
+	}
+
+	fn integer_only(&self) -> bool {
+		true
+	}
+
+	fn check_domain(&self, args: &[BigDec]) -> Result<(), String> {
+		let val = args[0].to_i128();
+		if val < std::i64::MIN as i128 || val > std::u64::MAX as i128 {
+			return Err("the argument must fit in 64 bits".to_string());
+		}
+		match args.get(1).map(|count| count.to_i128()) {
+			Some(count) if count < 1 || count > 8 => Err("the number of bytes must be from 1 to 8".to_string()),
+			_ => Ok(())
+		}
+	}
+
+	// Without a byte count, the fewest bytes holding x are used, negative
+	// values in two's complement: 0x1234 is 2 bytes, -1 is 0xFF.
+	// Bytes are taken most significant first unless le is 1.
+	fn call(&self, args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+		let word = args[0].to_i128() as u64;
+		let count = args.get(1).map_or(byte_count(args[0].to_i128()), |count| count.to_i128() as usize);
+		let little_endian = args.get(2).map_or(false, |le| !le.is_zero());
+		let mut bytes: Vec<u8> = (0..count).map(|i| (word >> (i * 8)) as u8).collect();
+		if !little_endian {
+			bytes.reverse();
+		}
+		from_u64((self.compute)(&bytes))
+	}
+
+	fn call_bytes(&self, bytes: &[u8]) -> Option<Result<BigDec, big_dec::Error>> {
+		Some(from_u64((self.compute)(bytes)))
+	}
+}
+
+#[test]
+fn test_check_values() {
+	let check = b"123456789";
+	assert_eq!(0xf4, crc8(check));
+	assert_eq!(0xbb3d, crc16(check));
+	assert_eq!(0x29b1, crc16ccitt(check));
+	assert_eq!(0x4b37, crc16modbus(check));
+	assert_eq!(0xcbf43926, crc32(check));
+	assert_eq!(0x091e01de, adler32(check));
+	assert_eq!(0xbb86b11c, fnv1a(check));
+	assert_eq!(0x06d5573923c6cdfc, fnv1a64(check));
+	assert_eq!(0, crc32(b""));
+}
+
+#[test]
+fn test_integer_bytes() {
+	let settings = Settings::new();
+	let crc32_func = CHECKSUMS.iter().find(|checksum| checksum.name == "crc32").unwrap();
+	let call = |args: &[i32]| {
+		let args: Vec<BigDec> = args.iter().map(|&arg| BigDec::from_i32(arg)).collect();
+		crc32_func.call(&args, &settings).ok().unwrap().to_string()
+	};
+	assert_eq!(0x18999699.to_string(), call(&[0x1234]));
+	assert_eq!(0x18999699.to_string(), call(&[0x1234, 2]));
+	assert_eq!(0x094a9040.to_string(), call(&[0x1234, 2, 1]));
+	assert_eq!(crc32(&[0x12, 0x34, 0x56, 0x78]).to_string(), call(&[0x12345678]));
+	assert_eq!(crc32(&[0, 0x12, 0x34]).to_string(), call(&[0x1234, 3]));
+	assert_eq!(crc32(&[0xff]).to_string(), call(&[-1]));
+	assert_eq!(crc32(&[0x80]).to_string(), call(&[0x80]));
+	assert_eq!(crc32(&[0x80]).to_string(), call(&[-0x80]));
+	assert_eq!(1, byte_count(0));
+	assert_eq!(8, byte_count(std::u64::MAX as i128));
+	assert_eq!(8, byte_count(std::i64::MIN as i128));
+	assert!(crc32_func.check_domain(&[BigDec::from_i32(1), BigDec::from_i32(9)]).is_err());
+}
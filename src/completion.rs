@@ -0,0 +1,114 @@
+use gtk;
+use gtk::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use funcs::Registry;
+use token;
+
+const COLUMN_NAME: u32 = 0;
+const COLUMN_HELP: u32 = 1;
+
+// Completes function names in the input box, and shows the description
+// of the function whose arguments are being typed
+pub struct FuncCompletion {
+	pub label_help: gtk::Label,
+	entry: gtk::Entry,
+	store: gtk::ListStore,
+	registry: Rc<RefCell<Registry>>
+}
+
+impl FuncCompletion {
+	pub fn new(entry: &gtk::Entry, registry: Rc<RefCell<Registry>>) -> Rc<FuncCompletion> {
+		let store = gtk::ListStore::new(&[gtk::Type::String, gtk::Type::String]);
+		let completion = gtk::EntryCompletion::new();
+		completion.set_model(&store);
+		completion.set_text_column(COLUMN_HELP as i32);
+		completion.set_minimum_key_length(1);
+		completion.set_inline_completion(false);
+		completion.set_popup_completion(true);
+		entry.set_completion(&completion);
+
+		let label_help = gtk::Label::new(None);
+		gtk::WidgetExt::set_name(&label_help, "help");
+		label_help.set_xalign(0.0);
+		label_help.set_no_show_all(true);
+
+		let fc = Rc::new(FuncCompletion {
+			label_help,
+			entry: entry.clone(),
+			store,
+			registry
+		});
+		fc.refresh();
+
+		// The key is the whole input, only the name before the cursor matters
+		completion.set_match_func({
+			let entry = entry.clone();
+			move |completion, _, iter| {
+				let text = entry.get_text().unwrap_or_default();
+				let (_, word) = match token::name_before(&text, entry.get_position() as usize) {
+					Some(name) => name,
+					None => return false
+				};
+				let name = completion.get_model()
+					.and_then(|model| model.get_value(iter, COLUMN_NAME as i32).get::<String>());
+				match name {
+					Some(name) => name.starts_with(&word),
+					None => false
+				}
+			}
+		});
+
+		// Replaces the name being typed with the function and its parenthesis
+		completion.connect_match_selected({
+			let entry = entry.clone();
+			move |_, model, iter| {
+				let name = match model.get_value(iter, COLUMN_NAME as i32).get::<String>() {
+					Some(name) => name,
+					None => return Inhibit(false)
+				};
+				let text = entry.get_text().unwrap_or_default();
+				let pos = entry.get_position();
+				if let Some((start, _)) = token::name_before(&text, pos as usize) {
+					entry.delete_text(start as i32, pos);
+					let mut insert_pos = start as i32;
+					entry.insert_text(&format!("{}(", name), &mut insert_pos);
+					entry.set_position(insert_pos);
+				}
+				Inhibit(true)
+			}
+		});
+
+		entry.connect_property_cursor_position_notify({
+			let fc = fc.clone();
+			move |_| { fc.update_help(); }
+		});
+		fc
+	}
+
+	// Fills the completion list again, to be called when functions
+	// are added or removed
+	pub fn refresh(&self) {
+		self.store.clear();
+		let registry = self.registry.borrow();
+		for name in registry.names() {
+			let help = registry.help(&name).unwrap_or_default();
+			self.store.insert_with_values(None, &[COLUMN_NAME, COLUMN_HELP], &[&name, &help]);
+		}
+		self.update_help();
+	}
+
+	// Shows the description of the function around the cursor, if any
+	fn update_help(&self) {
+		let text = self.entry.get_text().unwrap_or_default();
+		let help = token::func_at(&text, self.entry.get_position() as usize)
+			.and_then(|name| self.registry.borrow().help(&name));
+		match help {
+			Some(help) => {
+				self.label_help.set_text(&help);
+				self.label_help.show();
+			},
+			None => self.label_help.hide()
+		}
+	}
+}
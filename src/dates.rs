@@ -0,0 +1,259 @@
+use big_dec;
+use big_dec::BigDec;
+use funcs;
+use settings::Settings;
+use units::Quantity;
+
+const SECONDS_PER_DAY: i128 = 86400;
+
+// Units allowed in duration literals such as 1h30m, with their length
+// in seconds. Here m is a minute, not a meter, but only next to other
+// units: a lone m is the milli suffix, so 30 minutes are 30min.
+const DURATION_UNITS: [(&str, &str); 7] = [
+	("d", "86400"),
+	("h", "3600"),
+	("m", "60"),
+	("s", "1"),
+	("ms", "0.001"),
+	("us", "0.000001"),
+	("ns", "0.000000001"),
+];
+
+// Days since 1970-01-01 of a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i128, month: i128, day: i128) -> i128 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = if year >= 0 { year } else { year - 399 } / 400;
+	let year_of_era = year - era * 400;
+	let month_from_march = (month + 9) % 12;
+	let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+// Year, month and day of a number of days since 1970-01-01
+fn civil_from_days(days: i128) -> (i128, i128, i128) {
+	let days = days + 719468;
+	let era = if days >= 0 { days } else { days - 146096 } / 146097;
+	let day_of_era = days - era * 146097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_from_march = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+	let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+	(if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
+
+// Seconds since the Unix epoch of a date and time, in UTC. None if the
+// date does not exist, like 2026-02-30.
+pub fn to_unix(year: i128, month: i128, day: i128, hour: i128, minute: i128, second: i128) -> Option<BigDec> {
+	if month < 1 || month > 12 || day < 1 || hour > 23 || minute > 59 || second > 59 {
+		return None;
+	}
+	let days = days_from_civil(year, month, day);
+	if civil_from_days(days) != (year, month, day) {
+		return None;
+	}
+	BigDec::from_i128(days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second).ok()
+}
+
+// ISO text of a number of seconds since the Unix epoch, the time being
+// omitted at midnight: 2026-10-17 or 2026-10-17T08:30:00
+pub fn format_date(secs: BigDec) -> String {
+	let mut total = secs.to_i128();
+	if secs.is_negative() && !secs.is_integer() {
+		total -= 1; // the second started before
+	}
+	let days = total.div_euclid(SECONDS_PER_DAY);
+	let time = total.rem_euclid(SECONDS_PER_DAY);
+	let (year, month, day) = civil_from_days(days);
+	let date = format!("{:04}-{:02}-{:02}", year, month, day);
+	if time == 0 {
+		return date;
+	}
+	format!("{}T{:02}:{:02}:{:02}", date, time / 3600, time / 60 % 60, time % 60)
+}
+
+// Text of a number of seconds such as 1h23m14s, days being the largest
+// unit. The fraction of a second is kept: 0.25s.
+pub fn format_duration(secs: BigDec) -> String {
+	let magnitude = if secs.is_negative() { secs.negate() } else { secs };
+	let whole = magnitude.to_i128();
+	let mut ret = String::new();
+	if secs.is_negative() && !secs.is_zero() {
+		ret.push('-');
+	}
+	let pieces = [(whole / SECONDS_PER_DAY, "d"), (whole / 3600 % 24, "h"), (whole / 60 % 60, "m")];
+	for &(count, unit) in pieces.iter() {
+		if count != 0 {
+			ret.push_str(&format!("{}{}", count, unit));
+		}
+	}
+	let seconds = BigDec::sub(magnitude, BigDec::from_i128(whole - whole % 60).unwrap()).unwrap_or(magnitude);
+	// 30m alone would read back as milli, 30m0s does not
+	if !seconds.is_zero() || whole < 3600 {
+		ret.push_str(&format!("{}s", seconds.to_string()));
+	}
+	ret
+}
+
+// Length in seconds of a duration literal such as 1h30m or 250ms, first
+// being the number before the first unit and rest the text after it.
+// None when rest is not made of units and integers, or is a lone m.
+pub fn parse_duration(first: BigDec, rest: &str) -> Option<Result<BigDec, big_dec::Error>> {
+	if rest == "m" {
+		return None;
+	}
+	let mut pieces = Vec::new();
+	let mut count = first;
+	let mut chars = rest.chars().peekable();
+	while chars.peek().is_some() {
+		let mut unit = String::new();
+		while let Some(&c) = chars.peek() {
+			if !c.is_ascii_alphabetic() {
+				break;
+			}
+			unit.push(c);
+			chars.next();
+		}
+		let scale = match DURATION_UNITS.iter().find(|&&(name, _)| name == unit) {
+			Some(&(_, scale)) => BigDec::parse_str(scale).unwrap(),
+			None => return None
+		};
+		pieces.push((count, scale));
+
+		let mut digits = String::new();
+		while let Some(&c) = chars.peek() {
+			if !c.is_ascii_digit() {
+				break;
+			}
+			digits.push(c);
+			chars.next();
+		}
+		if digits.is_empty() {
+			break;
+		}
+		if chars.peek().is_none() {
+			return None; // a number without unit at the end
+		}
+		count = match BigDec::parse_str(&digits) {
+			Ok(count) => count,
+			Err(err) => return Some(Err(err))
+		};
+	}
+	if pieces.is_empty() || chars.peek().is_some() {
+		return None;
+	}
+	let mut total = BigDec::zero();
+	for (count, scale) in pieces {
+		total = match BigDec::mul(count, scale).and_then(|secs| BigDec::add(total, secs)) {
+			Ok(total) => total,
+			Err(err) => return Some(Err(err))
+		};
+	}
+	Some(Ok(total))
+}
+
+// Functions converting between dates and Unix timestamps. They take or
+// give dates, so expressions call them through call_quantities().
+#[derive(Clone)]
+pub struct DateFunc {
+	name: &'static str,
+	help: &'static str,
+	to_unix: bool // unixtime() rather than fromunix()
+}
+
+pub const DATE_FUNCS: [DateFunc; 2] = [
+	DateFunc { name: "fromunix", help: "fromunix(secs): date of a number of seconds since 1970-01-01, in UTC",
+		to_unix: false },
+	DateFunc { name: "unixtime", help: "unixtime(date): seconds since 1970-01-01, in UTC", to_unix: true },
+];
+
+impl funcs::Function for DateFunc {
+	fn name(&self) -> &str {
+		self.name
+	}
+
+	fn arity(&self) -> (usize, usize) {
+		(1, 1)
+	}
+
+	fn help(&self) -> &str {
+		self.help
+	}
+
+	// A plain number is never a date
+	fn check_domain(&self, _: &[BigDec]) -> Result<(), String> {
+		if self.to_unix { Err("expects a date".to_string()) } else { Ok(()) }
+	}
+
+	// Called without the date, the seconds stay as they are
+	fn call(&self, args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+		Ok(args[0])
+	}
+
+	fn call_quantities(&self, args: &[Quantity]) -> Option<Result<Quantity, funcs::Error>> {
+		let arg = args[0];
+		Some(if self.to_unix {
+			if arg.is_date() {
+				Ok(Quantity::plain(arg.value))
+			}
+			else {
+				Err(funcs::Error::OutOfDomain("unixtime expects a date".to_string()))
+			}
+		}
+		else {
+			match arg.to_plain() {
+				Ok(secs) => Ok(Quantity::date(secs)),
+				Err(_) => Err(funcs::Error::OutOfDomain("fromunix expects a number of seconds".to_string()))
+			}
+		})
+	}
+}
+
+#[test]
+fn test_civil_days() {
+	assert_eq!(0, days_from_civil(1970, 1, 1));
+	assert_eq!(20743, days_from_civil(2026, 10, 17));
+	assert_eq!(-719468, days_from_civil(0, 3, 1));
+	for &days in &[-800000, -1, 0, 59, 11016, 20743, 2932896] {
+		let (year, month, day) = civil_from_days(days);
+		assert_eq!(days, days_from_civil(year, month, day));
+	}
+	assert_eq!((2000, 2, 29), civil_from_days(11016));
+	assert!(to_unix(2026, 2, 29, 0, 0, 0).is_none());
+	assert!(to_unix(2024, 2, 29, 0, 0, 0).is_some());
+	assert!(to_unix(2026, 10, 17, 24, 0, 0).is_none());
+}
+
+#[test]
+fn test_format() {
+	let parse = |text: &str| BigDec::parse_str(text).unwrap();
+	assert_eq!("2023-11-14T22:13:20", format_date(parse("1700000000")));
+	assert_eq!("2026-01-01", format_date(to_unix(2026, 1, 1, 0, 0, 0).unwrap()));
+	assert_eq!("1969-12-31T23:59:59", format_date(parse("0.5").negate()));
+	assert_eq!("9h42m38s", format_duration(parse("34958")));
+	assert_eq!("1d1s", format_duration(parse("86401")));
+	assert_eq!("-1m30.5s", format_duration(parse("90.5").negate()));
+	assert_eq!("0.25s", format_duration(parse("0.25")));
+	assert_eq!("0s", format_duration(BigDec::zero()));
+	assert_eq!("30m0s", format_duration(parse("1800")));
+	assert_eq!("1h30m", format_duration(parse("5400")));
+}
+
+#[test]
+fn test_parse_duration() {
+	let secs = |first: i32, rest: &str| match parse_duration(BigDec::from_i32(first), rest) {
+		Some(Ok(secs)) => secs.to_string(),
+		_ => "none".to_string()
+	};
+	assert_eq!("5400", secs(1, "h30m"));
+	assert_eq!("4994", secs(1, "h23m14s"));
+	assert_eq!("90061", secs(1, "d1h1m1s"));
+	assert_eq!("2.25", secs(2, "s250ms"));
+	assert_eq!("0.25", secs(250, "ms"));
+	assert_eq!("none", secs(30, "m"));
+	assert_eq!("1800", secs(30, "m0s"));
+	assert_eq!("none", secs(2, "h30"));
+	assert_eq!("none", secs(2, "h30km"));
+	assert_eq!("none", secs(2, "x3y"));
+}
@@ -0,0 +1,169 @@
+use std;
+use std::fmt;
+use big_dec;
+use funcs;
+use units;
+
+// Range of characters in the input, the end is excluded
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize
+}
+
+impl Span {
+	pub fn new(start: usize, end: usize) -> Span {
+		Span { start, end }
+	}
+
+	// Empty range at a given position, used when something is missing
+	pub fn at(pos: usize) -> Span {
+		Span { start: pos, end: pos }
+	}
+
+	// Smallest range containing both spans
+	pub fn join(a: Span, b: Span) -> Span {
+		Span { start: std::cmp::min(a.start, b.start), end: std::cmp::max(a.end, b.end) }
+	}
+
+	// Converts the char range into a byte range of the text. An empty span
+	// is widened to the char before it so that there is something to show.
+	pub fn byte_range(&self, text: &str) -> (usize, usize) {
+		let mut start = self.start;
+		if self.start == self.end && start > 0 {
+			start -= 1;
+		}
+		let byte_at = |pos: usize| {
+			match text.char_indices().nth(pos) {
+				Some((index, _)) => index,
+				None => text.len()
+			}
+		};
+		(byte_at(start), byte_at(self.end))
+	}
+}
+
+// What went wrong, without the details. Callers can rely on it
+// to react to an error without parsing the message.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ErrorKind {
+	// tokenizing
+	BadChar,
+	BadNumber,
+	TooManyDigits,
+	TooManyDecimals,
+	BadDate,
+	BadCharLiteral,
+	BadString,
+	NameTooLong,
+
+	// parsing
+	MissingParClose,
+	ExpectedParClose,
+	MissingNumber,
+	ExpectedNumber,
+	UnexpectedToken,
+	MissingFactor,
+	MissingTerm,
+	MissingOperand,
+	MissingColon,
+	MissingFuncArg,
+	BadDefinition,
+
+	// evaluation
+	UnknownFunc,
+	UnknownVar,
+	WrongArgCount,
+	NotInteger,
+	OutOfDomain,
+	TooManyCalls,
+	DivideByZero,
+	Overflow,
+	IncompatibleUnits
+}
+
+impl ErrorKind {
+	pub fn from_big_dec(err: &big_dec::Error) -> ErrorKind {
+		match *err {
+			big_dec::Error::ParseNothing => ErrorKind::BadNumber,
+			big_dec::Error::ParseIntPartOverflow => ErrorKind::TooManyDigits,
+			big_dec::Error::ParseFracPartOverflow => ErrorKind::TooManyDecimals,
+			big_dec::Error::ParseBadChar => ErrorKind::BadNumber,
+			big_dec::Error::OpDivideByZero => ErrorKind::DivideByZero,
+			big_dec::Error::OpOverflow => ErrorKind::Overflow,
+			big_dec::Error::OpFactorialDomain => ErrorKind::OutOfDomain
+		}
+	}
+}
+
+// Error reported to the user, whatever the stage that failed
+#[derive(Clone,Debug)]
+pub struct Error {
+	kind: ErrorKind,
+	span: Span,
+	msg: String
+}
+
+impl Error {
+	pub fn new<S: Into<String>>(kind: ErrorKind, span: Span, msg: S) -> Error {
+		Error { kind, span, msg: msg.into() }
+	}
+
+	pub fn from_big_dec(err: big_dec::Error, span: Span) -> Error {
+		Error::new(ErrorKind::from_big_dec(&err), span, err.to_string())
+	}
+
+	pub fn from_func(err: funcs::Error, span: Span) -> Error {
+		match err {
+			funcs::Error::CallFailed(bd_err) => Error::from_big_dec(bd_err, span),
+			funcs::Error::UnknownFunc => Error::new(ErrorKind::UnknownFunc, span, err.to_string()),
+			funcs::Error::WrongArgCount { .. } => Error::new(ErrorKind::WrongArgCount, span, err.to_string()),
+			funcs::Error::NotInteger(_) => Error::new(ErrorKind::NotInteger, span, err.to_string()),
+			funcs::Error::OutOfDomain(_) => Error::new(ErrorKind::OutOfDomain, span, err.to_string())
+		}
+	}
+
+	pub fn from_units(err: units::Error, span: Span) -> Error {
+		match err {
+			units::Error::Op(bd_err) => Error::from_big_dec(bd_err, span),
+			units::Error::NameTooLong => Error::new(ErrorKind::NameTooLong, span, err.to_string()),
+			_ => Error::new(ErrorKind::IncompatibleUnits, span, err.to_string())
+		}
+	}
+
+	// Same error reported elsewhere, used when the faulty input is not
+	// the one being evaluated, like the body of a user function
+	pub fn with_span(self, span: Span) -> Error {
+		Error { span, ..self }
+	}
+
+	pub fn kind(&self) -> ErrorKind {
+		self.kind
+	}
+
+	pub fn span(&self) -> Span {
+		self.span
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.msg)
+	}
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn test_span_byte_range() {
+	assert_eq!((2, 4), Span::new(2, 4).byte_range("1+23"));
+	assert_eq!((3, 4), Span::at(4).byte_range("1+23"));
+	assert_eq!((0, 0), Span::at(0).byte_range(""));
+	assert_eq!((3, 4), Span::new(2, 3).byte_range("\u{e9}+$"));
+}
+
+#[test]
+fn test_span_join() {
+	assert_eq!(Span::new(1, 7), Span::join(Span::new(4, 7), Span::new(1, 2)));
+	assert_eq!(Span::new(3, 3), Span::join(Span::at(3), Span::at(3)));
+}
@@ -1,52 +1,98 @@
 use text_canvas::TextCanvas;
-use big_dec;
 use big_dec::BigDec;
 use token;
-use token::Token;
+use token::{Token, TokenSpan};
 use funcs;
+use error::{Error, ErrorKind, Span};
+use settings::Settings;
+use format;
+use user_funcs::UserFunc;
+use units;
+use units::Quantity;
+use dates;
+
+const MAX_CALL_DEPTH: usize = 100; // nested calls of user functions
 
 struct Node {
     token: Token,
+    span: Span,
     left_id: Option<usize>,
     right_id: Option<usize>
 }
 
 struct TreeArena {
 	nodes: Vec<Node>,
+	strings: Vec<String>, // texts of the string literals, see Token::Str
 }
 
 impl TreeArena {
 	fn new_with_size(size: usize) -> TreeArena {
-		TreeArena { nodes: Vec::with_capacity(size) }
+		TreeArena { nodes: Vec::with_capacity(size), strings: Vec::new() }
 	}
-	fn alloc_node(&mut self, token: Token) -> (&mut Node, usize) {
-		self.nodes.push( Node { token: token, left_id: None, right_id: None } );
+	fn alloc_node(&mut self, token: Token, span: Span) -> (&mut Node, usize) {
+		self.nodes.push( Node { token: token, span: span, left_id: None, right_id: None } );
 		let id = self.nodes.len() - 1;
 		(&mut self.nodes[id], id)
 	}
-	fn alloc_leaf(&mut self, token: Token) -> usize {
-		self.nodes.push( Node { token: token, left_id: None, right_id: None } );
+	fn alloc_leaf(&mut self, token: Token, span: Span) -> usize {
+		self.nodes.push( Node { token: token, span: span, left_id: None, right_id: None } );
 		self.nodes.len() - 1
 	}
 }
 
-enum EvalError {
-	Bd(big_dec::Error),
-	Fn(funcs::Error)
+pub struct Tree {
+    arena: TreeArena,
+	root_id: usize,
 }
 
-impl EvalError {
-	fn to_string(&self) -> String {
-		match *self {
-			EvalError::Bd(ref nv_error) => nv_error.to_string(),
-			EvalError::Fn(ref fn_error) => fn_error.to_string()
+// What the evaluation of a tree depends on besides the tree
+struct Scope<'a> {
+	settings: &'a Settings,
+	registry: &'a funcs::Registry,
+	params: &'a [String], // of the user function being evaluated
+	args: &'a [Quantity],
+	depth: usize // of user function calls
+}
+
+impl<'a> Scope<'a> {
+	// A parameter, a constant, or one of a unit such as the km of 5 km
+	fn get_var(&self, name: token::Name, span: Span) -> Result<Quantity, Error> {
+		let name_str = name.to_string();
+		if let Some(index) = self.params.iter().position(|param| *param == name_str) {
+			return Ok(self.args[index]);
+		}
+		if let Some(val) = funcs::constant(&name_str) {
+			return Ok(Quantity::plain(val));
+		}
+		match units::find(&name_str) {
+			Some(unit) => Ok(Quantity { value: BigDec::from_i32(1), unit: Some(unit) }),
+			None => Err(Error::new(ErrorKind::UnknownVar, span, format!("unknown variable: {}", name_str)))
 		}
 	}
 }
 
-struct Tree {
-    arena: TreeArena,
-	root_id: usize,
+// 1 for true, 0 for false
+fn from_bool(val: bool) -> Quantity {
+	Quantity::plain(BigDec::from_i32(if val { 1 } else { 0 }))
+}
+
+// Value of an operand that cannot have a unit
+fn plain(val: Quantity, span: Span) -> Result<BigDec, Error> {
+	val.to_plain().map_err(|err| Error::from_units(err, span))
+}
+
+// Whether a comparison operator holds, order being the result of
+// BigDec::compare_signed()
+fn comparison_holds(token: Token, order: i32) -> bool {
+	match token {
+		Token::Eq => order == 0,
+		Token::NotEq => order != 0,
+		Token::Less => order < 0,
+		Token::LessEq => order <= 0,
+		Token::Greater => order > 0,
+		Token::GreaterEq => order >= 0,
+		_ => false
+	}
 }
 
 impl Tree {
@@ -96,159 +142,451 @@ impl Tree {
 		canvas.to_string()
 	}
 
-	fn eval_node(&self, node_id: usize) -> Result<BigDec, EvalError> {
+	fn eval_node(&self, node_id: usize, scope: &Scope) -> Result<Quantity, Error> {
 		let node = self.get_node(node_id);
 
+		match node.token {
+			Token::Func(name) => return self.eval_call(node, name, scope),
+			Token::Var(name) => return scope.get_var(name, node.span),
+			Token::Question => return self.eval_conditional(node, scope),
+			Token::LogicAnd | Token::LogicOr => return self.eval_logic(node, scope),
+			Token::Str(_) => return Err(Error::new(ErrorKind::UnexpectedToken, node.span,
+				"a string can only be the argument of a checksum function")),
+			_ => ()
+		}
+
 		let val_left = if let Some(left_id) = node.left_id {
-			try!(self.eval_node(left_id))
+			try!(self.eval_node(left_id, scope))
 		}
 		else {
-			BigDec::zero()
+			Quantity::plain(BigDec::zero())
 		};
 
 		let val_right = if let Some(right_id) = node.right_id {
-			try!(self.eval_node(right_id))
+			try!(self.eval_node(right_id, scope))
 		}
 		else {
-			BigDec::zero()
+			Quantity::plain(BigDec::zero())
+		};
+		self.apply_operator(node, val_left, val_right)
+	}
+
+	// Kept out of eval_node() so that deep recursions of user functions
+	// do not pile up its temporaries on the stack
+	fn apply_operator(&self, node: &Node, val_left: Quantity, val_right: Quantity) -> Result<Quantity, Error> {
+		// Sums and comparisons need both operands in the same unit
+		let common_values = || Quantity::common_values(val_left, val_right)
+			.map_err(|err| Error::from_units(err, node.span));
+		let in_unit = |res: Result<BigDec, ::big_dec::Error>, unit: Option<units::Unit>| match res {
+			Ok(value) => Ok(Quantity { value, unit }),
+			Err(err) => Err(Error::from_big_dec(err, node.span))
 		};
 
 		let nv_result = match node.token {
-			Token::Nothing => Ok(BigDec::zero()),
-			Token::Number(ref nv) => Ok(*nv),
-			Token::ParOpen => Ok(BigDec::zero()),
-			Token::ParClose => Ok(BigDec::zero()),
-			Token::Add => match BigDec::add(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
-			},
-			Token::Sub => match BigDec::sub(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
-			},
-			Token::Mul => match BigDec::mul(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
+			Token::Nothing => Ok(Quantity::plain(BigDec::zero())),
+			Token::Number(ref nv) => Ok(Quantity::plain(*nv)),
+			Token::Date(secs) => Ok(Quantity::date(secs)),
+			Token::Duration(secs) => Ok(Quantity::duration(secs)),
+			Token::ParOpen => Ok(Quantity::plain(BigDec::zero())),
+			Token::ParClose => Ok(Quantity::plain(BigDec::zero())),
+			Token::Add => Quantity::add(val_left, val_right).map_err(|err| Error::from_units(err, node.span)),
+			Token::Sub => Quantity::sub(val_left, val_right).map_err(|err| Error::from_units(err, node.span)),
+			Token::Mul => Quantity::mul(val_left, val_right).map_err(|err| Error::from_units(err, node.span)),
+			Token::Div => Quantity::div(val_left, val_right).map_err(|err| Error::from_units(err, node.span)),
+			Token::Mod => {
+				let (left, right, unit) = try!(common_values());
+				in_unit(BigDec::div_mod(left, right), unit)
 			},
-			Token::Div => match BigDec::div(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
+			Token::Convert => val_left.convert(val_right).map_err(|err| Error::from_units(err, node.span)),
+			Token::Func(_) | Token::Var(_) | Token::Str(_) | Token::Comma | Token::Assign | Token::Question
+				| Token::Colon | Token::LogicAnd | Token::LogicOr => Ok(Quantity::plain(BigDec::zero())),
+			Token::Fact => match funcs::factorial(try!(plain(val_left, node.span))) {
+				Ok(val) => Ok(Quantity::plain(val)),
+				Err(err) => Err(Error::from_func(err, node.span))
 			},
-			Token::Mod => match BigDec::div_mod(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
+			Token::DoubleFact => match funcs::double_factorial(try!(plain(val_left, node.span))) {
+				Ok(val) => Ok(Quantity::plain(val)),
+				Err(err) => Err(Error::from_func(err, node.span))
 			},
-			Token::Func(name) => match funcs::eval_func(name, val_left) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Fn(err))
+			Token::And => {
+				let res = BigDec::and(try!(plain(val_left, node.span)), try!(plain(val_right, node.span)));
+				in_unit(res, None)
 			},
-			Token::Fact => match BigDec::fact(val_left) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
+			Token::Eq | Token::NotEq | Token::Less | Token::LessEq | Token::Greater | Token::GreaterEq => {
+				let (left, right) = try!(Quantity::compared_values(val_left, val_right)
+					.map_err(|err| Error::from_units(err, node.span)));
+				let order = BigDec::compare_signed(left, right);
+				Ok(from_bool(comparison_holds(node.token, order)))
 			},
-			Token::And => match BigDec::and(val_left, val_right) {
-				Ok(val) => Ok(val),
-				Err(err) => Err(EvalError::Bd(err))
-			}
+			Token::Not => Ok(from_bool(val_left.value.is_zero()))
 		};
 		nv_result
 	}
 
-	fn eval(&self) -> Result<BigDec, EvalError> {
-		self.eval_node(self.root_id)
+	// Evaluates the condition, then only the chosen branch so that
+	// x != 0 ? 1/x : 0 does not divide by zero
+	fn eval_conditional(&self, node: &Node, scope: &Scope) -> Result<Quantity, Error> {
+		let cond = try!(self.eval_node(node.left_id.unwrap(), scope));
+		let branches = self.get_node(node.right_id.unwrap());
+		let branch_id = if cond.value.is_zero() { branches.right_id } else { branches.left_id };
+		self.eval_node(branch_id.unwrap(), scope)
+	}
+
+	// Evaluates the right operand only when the left one does not decide
+	fn eval_logic(&self, node: &Node, scope: &Scope) -> Result<Quantity, Error> {
+		let left = try!(self.eval_node(node.left_id.unwrap(), scope)).value;
+		let decided = match node.token {
+			Token::LogicAnd => left.is_zero(),
+			_ => !left.is_zero()
+		};
+		if decided {
+			return Ok(from_bool(!left.is_zero()));
+		}
+		let right = try!(self.eval_node(node.right_id.unwrap(), scope)).value;
+		Ok(from_bool(!right.is_zero()))
+	}
+
+	// Arguments of a function, kept in a chain of Comma nodes when
+	// there are several of them
+	fn arg_ids(&self, node_id: usize) -> Vec<usize> {
+		let mut ret = Vec::new();
+		let mut id = node_id;
+		loop {
+			let node = self.get_node(id);
+			match (node.token, node.left_id, node.right_id) {
+				(Token::Comma, Some(left_id), Some(right_id)) => {
+					ret.push(left_id);
+					id = right_id;
+				},
+				_ => {
+					ret.push(id);
+					break;
+				}
+			}
+		}
+		ret
+	}
+
+	fn eval_call(&self, node: &Node, name: token::Name, scope: &Scope) -> Result<Quantity, Error> {
+		let name_str = name.to_string();
+
+		// A checksum of a string, such as crc32("abc")
+		if let Some(Token::Str(index)) = node.left_id.map(|left_id| self.get_node(left_id).token) {
+			if scope.registry.get(&name_str).is_some() {
+				let text = &self.arena.strings[index];
+				return match scope.registry.call_bytes(&name_str, text.as_bytes()) {
+					Ok(val) => Ok(Quantity::plain(val)),
+					Err(err) => Err(Error::from_func(err, node.span))
+				};
+			}
+		}
+
+		let mut args = Vec::new();
+		if let Some(left_id) = node.left_id {
+			for arg_id in self.arg_ids(left_id) {
+				args.push(try!(self.eval_node(arg_id, scope)));
+			}
+		}
+
+		let user_func = match scope.registry.user_funcs.get(&name_str) {
+			Some(func) if scope.registry.get(&name_str).is_none() => func,
+			_ => {
+				if let Some(res) = scope.registry.call_quantities(&name_str, &args) {
+					return res.map_err(|err| Error::from_func(err, node.span));
+				}
+
+				// Only user functions take quantities with units
+				let mut values = Vec::new();
+				for arg in &args {
+					values.push(try!(plain(*arg, node.span)));
+				}
+				return match scope.registry.call(&name_str, &values, scope.settings) {
+					Ok(val) => Ok(Quantity::plain(val)),
+					Err(err) => Err(Error::from_func(err, node.span))
+				};
+			}
+		};
+		let param_count = user_func.params.len();
+		if args.len() != param_count {
+			let err = funcs::Error::WrongArgCount { name: name_str, min: param_count, max: param_count,
+				count: args.len() };
+			return Err(Error::from_func(err, node.span));
+		}
+
+		if scope.depth == MAX_CALL_DEPTH {
+			return Err(Error::new(ErrorKind::TooManyCalls, node.span,
+				format!("too many nested calls of {}", name_str)));
+		}
+		let func_scope = Scope {
+			settings: scope.settings,
+			registry: scope.registry,
+			params: &user_func.params,
+			args: &args,
+			depth: scope.depth + 1
+		};
+		// Errors are reported on the call since the body is not in the input
+		user_func.tree.eval_node(user_func.tree.root_id, &func_scope).map_err(|err| err.with_span(node.span))
+	}
+
+	fn eval(&self, settings: &Settings, registry: &funcs::Registry) -> Result<Quantity, Error> {
+		let scope = Scope { settings, registry, params: &[], args: &[], depth: 0 };
+		self.eval_node(self.root_id, &scope)
+	}
+
+	// First variable that is neither one of the parameters nor a
+	// constant nor a unit, if any
+	fn find_unknown_var(&self, params: &[String]) -> Option<Span> {
+		for node in &self.arena.nodes {
+			if let Token::Var(name) = node.token {
+				let name_str = name.to_string();
+				if !params.contains(&name_str) && funcs::constant(&name_str).is_none() && !units::is_unit(&name_str) {
+					return Some(node.span);
+				}
+			}
+		}
+		None
 	}
 }
 
 struct TokenGetter<'a> {
-	tokens: &'a Vec<Token>,
+	input: &'a str, // the spans of the tokens are positions in it
+	tokens: &'a [TokenSpan],
+	registry: &'a funcs::Registry,
+	params: &'a [String], // of the function being defined
 	index: usize,
+	implicit_mul: bool // a factor directly followed by another one is a product
 }
 
 impl<'a> TokenGetter<'a> {
+	// Whether a name is a parameter or a constant, which takes precedence
+	// over the suffixes of numbers
+	fn is_variable(&self, name: &str) -> bool {
+		self.params.iter().any(|param| param == name) || funcs::constant(name).is_some()
+	}
+
+	// Whether name followed by a parenthesis is a call, rather than the
+	// product of a variable and a parenthesis like pi(2). Parameters and
+	// constants are variables, functions come before units so that min
+	// is a function there. Unknown names stay calls, so that functions
+	// defined later can be used and typos are reported as such.
+	fn is_func(&self, name: &str) -> bool {
+		if self.is_variable(name) {
+			return false;
+		}
+		let known = self.registry.get(name).is_some() || self.registry.user_funcs.get(name).is_some();
+		known || !units::is_unit(name)
+	}
+
 	fn peek(&mut self) -> Option<&'a Token> {
-		self.tokens.get(self.index)
+		self.tokens.get(self.index).map(|ts| &ts.token)
 	}
 
 	fn next(&mut self) -> Option<&'a Token> {
 		if let Some(ret) = self.tokens.get(self.index) {
 			self.index += 1;
-			Some(ret)
+			Some(&ret.token)
 		}
 		else {
 			None
 		}
 	}
+
+	// Span of the last token returned by next()
+	fn span(&self) -> Span {
+		if self.index == 0 {
+			Span::at(0)
+		}
+		else {
+			self.tokens[self.index - 1].span
+		}
+	}
+
+	// Empty span before the next token
+	fn next_span(&self) -> Span {
+		match self.tokens.get(self.index) {
+			Some(ts) => Span::at(ts.span.start),
+			None => self.end_span()
+		}
+	}
+
+	// Empty span after the last token, for missing things
+	fn end_span(&self) -> Span {
+		match self.tokens.last() {
+			Some(ts) => Span::at(ts.span.end),
+			None => Span::at(0)
+		}
+	}
 }
 
 enum ParseResult {
 	None,
-	Fail(String),
+	Fail(Error),
 	Some(usize),
 }
 
 fn parse_subfactor_parenthesis(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 	// Parenthesis expression
 	let inside = parse_expression(tg, arena);
+	if let ParseResult::Fail(err) = inside {
+		return ParseResult::Fail(err);
+	}
 
 	// We expect the closing parenthesis
 	let op2 = match tg.next() {
 		Some(op2) => op2,
 		None => {
-			return ParseResult::Fail(format!("missing ')'"));
+			return ParseResult::Fail(Error::new(ErrorKind::MissingParClose,
+				tg.end_span(), "missing ')'"));
 		}
 	};
 
 	match *op2 {
 		Token::ParClose => (),
 		_ => {
-			return ParseResult::Fail(format!("expected ')', found: {}", op2.to_string()));
+			return ParseResult::Fail(Error::new(ErrorKind::ExpectedParClose,
+				tg.span(), format!("expected ')', found: {}", op2.to_string())));
 		}
 	}
 	return inside;
 }
 
 fn parse_subfactor_function(tg: &mut TokenGetter, arena: &mut TreeArena, name: token::Name) -> ParseResult {
-	// Parenthesis expression
-	let inside_id_res = parse_expression(tg, arena);
-	let inside_id = match inside_id_res {
-		ParseResult::None => {
-			// Missing function argument
-			return ParseResult::Fail(format!("missing function argument"));
-		},
-		ParseResult::Fail(err) => {
-			return ParseResult::Fail(err)
-		},
-		ParseResult::Some(inside_id) => {
-			// Ok, continue
-			inside_id
+	let name_span = tg.span();
+
+	// Arguments separated by commas
+	let mut arg_ids = Vec::new();
+	let mut missing_span = name_span;
+	loop {
+		match parse_expression(tg, arena) {
+			ParseResult::None => {
+				// Missing function argument
+				return ParseResult::Fail(Error::new(ErrorKind::MissingFuncArg,
+					missing_span, "missing function argument"));
+			},
+			ParseResult::Fail(err) => {
+				return ParseResult::Fail(err)
+			},
+			ParseResult::Some(arg_id) => {
+				// Ok, continue
+				arg_ids.push(arg_id);
+			}
+		};
+		match tg.peek() {
+			Some(&Token::Comma) => {
+				tg.next();
+				missing_span = tg.span();
+			},
+			_ => break
 		}
-	};
+	}
 
 	// We expect the closing parenthesis
 	let op2 = match tg.next() {
 		Some(op2) => op2,
 		None => {
-			return ParseResult::Fail(format!("missing ')'"));
+			return ParseResult::Fail(Error::new(ErrorKind::MissingParClose,
+				tg.end_span(), "missing ')'"));
 		}
 	};
 
 	match *op2 {
 		Token::ParClose => (),
 		_ => {
-			return ParseResult::Fail(format!("expected ')', found: {}", op2.to_string()));
+			return ParseResult::Fail(Error::new(ErrorKind::ExpectedParClose,
+				tg.span(), format!("expected ')', found: {}", op2.to_string())));
 		}
 	}
 
+	// if(cond, a, b) is cond ? a : b, so that only one of a and b is evaluated
+	if name.as_str() == "if" {
+		if arg_ids.len() != 3 {
+			let err = funcs::Error::WrongArgCount { name: name.to_string(), min: 3, max: 3, count: arg_ids.len() };
+			return ParseResult::Fail(Error::from_func(err, name_span));
+		}
+		return ParseResult::Some(alloc_conditional(arena, arg_ids[0], arg_ids[1], arg_ids[2], name_span));
+	}
+
+	// Chain the arguments with Comma nodes, the last one first
+	let mut inside_id = arg_ids.pop().unwrap();
+	while let Some(arg_id) = arg_ids.pop() {
+		let span = arena.nodes[arg_id].span;
+		let (node, node_id) = arena.alloc_node(Token::Comma, span);
+		node.left_id = Some(arg_id);
+		node.right_id = Some(inside_id);
+		inside_id = node_id;
+	}
+
 	// Alloc a node to keep the function name and the subtree inside the parenthesis
-	let (node, node_id) = arena.alloc_node(Token::Func(name));
+	let (node, node_id) = arena.alloc_node(Token::Func(name), name_span);
 	node.left_id = Some(inside_id);
 	node.right_id = None;
 	ParseResult::Some(node_id)
 }
 
-// S -> '-'? number
+// Name followed by a parenthesis that is not a function call, such as
+// pi(2) or the x(x + 1) of f(x) = x(x + 1): the product of the variable
+// and the parenthesis, the '(' being already read with the name
+fn parse_variable_product(tg: &mut TokenGetter, arena: &mut TreeArena, name: token::Name) -> ParseResult {
+	let span = tg.span();
+	let mul_span = Span::at(span.end - 1);
+	let right_id = match parse_subfactor_parenthesis(tg, arena) {
+		ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingFactor,
+			mul_span, "missing factor")),
+		ParseResult::Fail(err) => return ParseResult::Fail(err),
+		ParseResult::Some(right_id) => right_id
+	};
+	let var_id = arena.alloc_leaf(Token::Var(name), Span::new(span.start, span.end - 1));
+	let (node, node_id) = arena.alloc_node(Token::Mul, mul_span);
+	node.left_id = Some(var_id);
+	node.right_id = Some(right_id);
+	ParseResult::Some(node_id)
+}
+
+// Number leaf followed without space by a name that completes it: the
+// rest of a duration such as the h30m of 1h30m or the ms of 250ms, or
+// a magnitude suffix such as the k of 4k. Glued to the number, m is the
+// milli suffix rather than the meter, so that 250m reads back. The leaf
+// becomes a duration or takes the scaled value. Parameters and constants
+// keep their meaning, so that 2n is a product in f(n) = 2n + 1.
+fn parse_number_suffix(tg: &mut TokenGetter, arena: &mut TreeArena, node_id: usize) -> ParseResult {
+	let (value, span) = match arena.nodes[node_id] {
+		Node { token: Token::Number(value), span, .. } => (value, span),
+		_ => return ParseResult::Some(node_id)
+	};
+	let (name, suffix_span) = match tg.tokens.get(tg.index) {
+		Some(&TokenSpan { token: Token::Var(name), span: suffix_span })
+			if suffix_span.start == span.end && !tg.is_variable(name.as_str()) => (name, suffix_span),
+		_ => return ParseResult::Some(node_id)
+	};
+	let magnitude = if value.is_negative() { value.negate() } else { value };
+	let res = if let Some(secs) = dates::parse_duration(magnitude, name.as_str()) {
+		secs.map(|secs| Token::Duration(if value.is_negative() { secs.negate() } else { secs }))
+	}
+	else {
+		match units::magnitude(name.as_str()) {
+			Some(scale) => BigDec::mul(value, scale).map(Token::Number),
+			_ => return ParseResult::Some(node_id)
+		}
+	};
+	tg.next();
+	let node = &mut arena.nodes[node_id];
+	node.span = Span::join(span, suffix_span);
+	match res {
+		Ok(token) => {
+			node.token = token;
+			ParseResult::Some(node_id)
+		},
+		Err(err) => ParseResult::Fail(Error::from_big_dec(err, node.span))
+	}
+}
+
+// S -> '-'? number suffix?
+// S -> date
+// S -> string
 // S -> '(' X ')'
-// S -> 'func(' X ')'
+// S -> 'func(' X { ',' X }* ')'
+// S -> var
+// S -> '!' S
 fn parse_subfactor(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 	let op = match tg.next() {
 		Some(op) => op,
@@ -257,39 +595,77 @@ fn parse_subfactor(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 
 	match *op {
 		Token::Sub => {
+			let sub_span = tg.span();
 			// Wants number
 			let op_next = match tg.next() {
 				Some(op_next) => op_next,
-				None => { return ParseResult::Fail("missing number".into()); }
+				None => {
+					return ParseResult::Fail(Error::new(ErrorKind::MissingNumber,
+						tg.end_span(), "missing number"));
+				}
 			};
 			match *op_next {
 				Token::Number(nv) => {
-					let node_id = arena.alloc_leaf(Token::Number(nv.negate()));
-					return ParseResult::Some(node_id);
+					let span = Span::join(sub_span, tg.span());
+					let node_id = arena.alloc_leaf(Token::Number(nv.negate()), span);
+					return parse_number_suffix(tg, arena, node_id);
 				},
 				_ => {
-					return ParseResult::Fail(format!(
-						"expected number instead of {}", op_next.to_string()));
+					return ParseResult::Fail(Error::new(ErrorKind::ExpectedNumber,
+						tg.span(), format!("expected number instead of {}", op_next.to_string())));
 				}
 			}
 		}
 		Token::Number(nv) => {
-			let node_id = arena.alloc_leaf(Token::Number(nv));
+			let node_id = arena.alloc_leaf(Token::Number(nv), tg.span());
+			return parse_number_suffix(tg, arena, node_id);
+		},
+		Token::Date(_) => {
+			let node_id = arena.alloc_leaf(*op, tg.span());
+			return ParseResult::Some(node_id);
+		},
+		Token::Str(_) => {
+			let span = tg.span();
+			let literal: String = tg.input.chars().skip(span.start).take(span.end - span.start).collect();
+			arena.strings.push(token::string_value(&literal));
+			let node_id = arena.alloc_leaf(Token::Str(arena.strings.len() - 1), span);
 			return ParseResult::Some(node_id);
 		},
 		Token::ParOpen => {
 			return parse_subfactor_parenthesis(tg, arena);
 		},
 		Token::Func(name) => {
+			if tg.implicit_mul && !tg.is_func(name.as_str()) {
+				return parse_variable_product(tg, arena, name);
+			}
 			return parse_subfactor_function(tg, arena, name);
 		},
+		Token::Var(name) => {
+			let node_id = arena.alloc_leaf(Token::Var(name), tg.span());
+			return ParseResult::Some(node_id);
+		},
+		Token::Fact => {
+			// Before its operand this is a logical not
+			let not_span = tg.span();
+			let operand_id = match parse_subfactor(tg, arena) {
+				ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingOperand,
+					not_span, "missing operand after !")),
+				ParseResult::Fail(err) => return ParseResult::Fail(err),
+				ParseResult::Some(operand_id) => operand_id
+			};
+			let (node, node_id) = arena.alloc_node(Token::Not, not_span);
+			node.left_id = Some(operand_id);
+			return ParseResult::Some(node_id);
+		},
 		_ => {
-			return ParseResult::Fail(format!("unexpected {}", op.to_string()));
+			return ParseResult::Fail(Error::new(ErrorKind::UnexpectedToken,
+				tg.span(), format!("unexpected {}", op.to_string())));
 		},
 	}
 }
 
 // F -> S '!'?
+// F -> S '!!'
 fn parse_factor(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 	let sf_id = match parse_subfactor(tg, arena) {
 		ParseResult::None => { return ParseResult::None; },
@@ -309,16 +685,70 @@ fn parse_factor(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 		},
 		_ => { return ParseResult::Some(sf_id); }
 	}
+	let mut fact_token = Token::Fact;
+	let mut fact_span = tg.span();
+
+	// A second '!' makes a double factorial
+	if let Some(&Token::Fact) = tg.peek() {
+		tg.next();
+		fact_token = Token::DoubleFact;
+		fact_span = Span::join(fact_span, tg.span());
+	}
 
-	let (node, node_id) = arena.alloc_node(Token::Fact);
+	let (node, node_id) = arena.alloc_node(fact_token, fact_span);
 	node.left_id = Some(sf_id);
 	node.right_id = None;
 	ParseResult::Some(node_id)
 }
 
-// { * F }*
-// { / F }*
-// { % F }*
+// Tokens that can start the right factor of an implicit product,
+// such as the pi of 2pi
+fn starts_implicit_factor(token: &Token) -> bool {
+	match *token {
+		Token::ParOpen | Token::Func(_) | Token::Var(_) => true,
+		_ => false
+	}
+}
+
+// Whether the token is the name of a unit, such as the km of 5 km
+fn is_unit_token(token: &Token) -> bool {
+	match *token {
+		Token::Var(name) => units::is_unit(name.as_str()),
+		_ => false
+	}
+}
+
+// P -> F { F }*
+// The factors are multiplied without '*', when the settings allow it or
+// when the right one is a unit. This binds tighter than '*' and '/' so
+// that 1/2pi is 1/(2*pi) and 100 km / 2 h is a speed.
+fn parse_product(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	let mut root_id = match parse_factor(tg, arena) {
+		ParseResult::Some(id) => id,
+		other => return other
+	};
+	loop {
+		match tg.peek() {
+			Some(op) if tg.implicit_mul && starts_implicit_factor(op) => (),
+			Some(op) if is_unit_token(op) => (),
+			_ => break
+		}
+		let mul_span = tg.next_span();
+		let right_id = match parse_factor(tg, arena) {
+			ParseResult::Some(right_id) => right_id,
+			other => return other
+		};
+		let (node, node_id) = arena.alloc_node(Token::Mul, mul_span);
+		node.left_id = Some(root_id);
+		node.right_id = Some(right_id);
+		root_id = node_id;
+	}
+	ParseResult::Some(root_id)
+}
+
+// { * P }*
+// { / P }*
+// { % P }*
 fn parse_term_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_id: usize) -> ParseResult {
 	loop {
 		let op = match tg.peek() {
@@ -331,13 +761,15 @@ fn parse_term_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_id: us
 			_ => { break; }
 		}
 		tg.next();
+		let op_span = tg.span();
 
-		let right_id = match parse_factor(tg, arena) {
-			ParseResult::None => return ParseResult::Fail("missing factor".into()),
+		let right_id = match parse_product(tg, arena) {
+			ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingFactor,
+				op_span, "missing factor")),
 			ParseResult::Fail(err) => return ParseResult::Fail(err),
 			ParseResult::Some(right_id) => right_id
 		};
-		let (node, node_id) = arena.alloc_node((*op).clone());
+		let (node, node_id) = arena.alloc_node((*op).clone(), op_span);
 		node.left_id = Some(root_id);
 		node.right_id = Some(right_id);
 		root_id = node_id;
@@ -345,16 +777,16 @@ fn parse_term_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_id: us
 	ParseResult::Some(root_id)
 }
 
-// T -> F { * F }*
-// T -> F { / F }*
-// T -> F { % F }*
+// T -> P { * P }*
+// T -> P { / P }*
+// T -> P { % P }*
 fn parse_term(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
-	// F
-	match parse_factor(tg, arena) {
+	// P
+	match parse_product(tg, arena) {
 		ParseResult::None => ParseResult::None,
 		ParseResult::Fail(err) => ParseResult::Fail(err),
 		ParseResult::Some(root_id) =>  {
-			// { * F }
+			// { * P }
 			match parse_term_right(tg, arena, root_id) {
 				ParseResult::None => ParseResult::Some(root_id),
 				ParseResult::Fail(err) => ParseResult::Fail(err),
@@ -366,8 +798,8 @@ fn parse_term(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 	}
 }
 
-// { - T }*
-// { + T }*
+// { - T }*
+// { + T }*
 fn parse_expression_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_id: usize) -> ParseResult {
 	loop {
 		let op = match tg.peek() {
@@ -380,14 +812,16 @@ fn parse_expression_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_
 			_ => { break; }
 		}
 		tg.next();
+		let op_span = tg.span();
 
 		let right_id = match parse_term(tg, arena) {
-			ParseResult::None => return ParseResult::Fail("missing term".into()),
+			ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingTerm,
+				op_span, "missing term")),
 			ParseResult::Fail(err) => return ParseResult::Fail(err),
 			ParseResult::Some(right_id) => right_id
 		};
 
-		let (node, node_id) = arena.alloc_node((*op).clone());
+		let (node, node_id) = arena.alloc_node((*op).clone(), op_span);
 		node.left_id = Some(root_id);
 		node.right_id = Some(right_id);
 		root_id = node_id;
@@ -395,15 +829,15 @@ fn parse_expression_right(tg: &mut TokenGetter, arena: &mut TreeArena, mut root_
 	ParseResult::Some(root_id)
 }
 
-// X -> T { - T }*
-// X -> T { + T }*
-fn parse_expression(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+// A -> T { - T }*
+// A -> T { + T }*
+fn parse_sum(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
 	// T
 	match parse_term(tg, arena) {
 		ParseResult::None => ParseResult::None,
 		ParseResult::Fail(err) => ParseResult::Fail(err),
 		ParseResult::Some(root_id) => {
-			// { - T }
+			// { - T }
 			match parse_expression_right(tg, arena, root_id) {
 				ParseResult::None => ParseResult::Some(root_id),
 				ParseResult::Fail(err) => ParseResult::Fail(err),
@@ -415,87 +849,352 @@ fn parse_expression(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult
 	}
 }
 
-// creates the evaluation tree from the list of tokens
-fn make_tree(mut tokens: Vec<Token>) -> Result<Tree, String> {
+// One level of left associative binary operators: Y { op Y }*
+fn parse_binary(tg: &mut TokenGetter, arena: &mut TreeArena, is_op: fn(&Token) -> bool,
+	parse_operand: fn(&mut TokenGetter, &mut TreeArena) -> ParseResult) -> ParseResult {
+	let mut root_id = match parse_operand(tg, arena) {
+		ParseResult::Some(id) => id,
+		other => return other
+	};
+	loop {
+		let op = match tg.peek() {
+			Some(op) if is_op(op) => op,
+			_ => break
+		};
+		tg.next();
+		let op_span = tg.span();
+
+		let right_id = match parse_operand(tg, arena) {
+			ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingOperand,
+				op_span, format!("missing operand after {}", op.to_string()))),
+			ParseResult::Fail(err) => return ParseResult::Fail(err),
+			ParseResult::Some(right_id) => right_id
+		};
+		let (node, node_id) = arena.alloc_node(*op, op_span);
+		node.left_id = Some(root_id);
+		node.right_id = Some(right_id);
+		root_id = node_id;
+	}
+	ParseResult::Some(root_id)
+}
+
+// B -> A { & A }*
+fn parse_bit_and(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	parse_binary(tg, arena, |op| match *op { Token::And => true, _ => false }, parse_sum)
+}
+
+// C -> B { == B }*, same with != < <= > >=
+fn parse_comparison(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	let is_op = |op: &Token| match *op {
+		Token::Eq | Token::NotEq | Token::Less | Token::LessEq | Token::Greater | Token::GreaterEq => true,
+		_ => false
+	};
+	parse_binary(tg, arena, is_op, parse_bit_and)
+}
+
+// L -> C { && C }*
+fn parse_logic_and(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	parse_binary(tg, arena, |op| match *op { Token::LogicAnd => true, _ => false }, parse_comparison)
+}
+
+// O -> L { || L }*
+fn parse_logic_or(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	parse_binary(tg, arena, |op| match *op { Token::LogicOr => true, _ => false }, parse_logic_and)
+}
+
+// Node for cond ? a : b, a Question node whose right is a Colon node
+// holding the branches
+fn alloc_conditional(arena: &mut TreeArena, cond_id: usize, then_id: usize, else_id: usize, span: Span) -> usize {
+	let (colon, colon_id) = arena.alloc_node(Token::Colon, span);
+	colon.left_id = Some(then_id);
+	colon.right_id = Some(else_id);
+	let (node, node_id) = arena.alloc_node(Token::Question, span);
+	node.left_id = Some(cond_id);
+	node.right_id = Some(colon_id);
+	node_id
+}
+
+// Operand of '?' or ':'
+fn parse_branch(tg: &mut TokenGetter, arena: &mut TreeArena) -> Result<usize, Error> {
+	let op_span = tg.span();
+	match parse_expression(tg, arena) {
+		ParseResult::None => Err(Error::new(ErrorKind::MissingOperand, op_span,
+			format!("missing operand after {}", tg.tokens[tg.index - 1].token.to_string()))),
+		ParseResult::Fail(err) => Err(err),
+		ParseResult::Some(id) => Ok(id)
+	}
+}
+
+// X -> K { in T }*
+// The unit to convert to is a term, so that 90 km/h in m/s works
+fn parse_expression(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	let mut root_id = match parse_conditional(tg, arena) {
+		ParseResult::Some(id) => id,
+		other => return other
+	};
+	while let Some(&Token::Convert) = tg.peek() {
+		tg.next();
+		let op_span = tg.span();
+		let right_id = match parse_term(tg, arena) {
+			ParseResult::None => return ParseResult::Fail(Error::new(ErrorKind::MissingOperand,
+				op_span, "missing unit after in")),
+			ParseResult::Fail(err) => return ParseResult::Fail(err),
+			ParseResult::Some(right_id) => right_id
+		};
+		let (node, node_id) = arena.alloc_node(Token::Convert, op_span);
+		node.left_id = Some(root_id);
+		node.right_id = Some(right_id);
+		root_id = node_id;
+	}
+	ParseResult::Some(root_id)
+}
+
+// K -> O { '?' X ':' X }?
+fn parse_conditional(tg: &mut TokenGetter, arena: &mut TreeArena) -> ParseResult {
+	let cond_id = match parse_logic_or(tg, arena) {
+		ParseResult::Some(id) => id,
+		other => return other
+	};
+	match tg.peek() {
+		Some(&Token::Question) => { tg.next(); },
+		_ => return ParseResult::Some(cond_id)
+	}
+	let question_span = tg.span();
+	let then_id = match parse_branch(tg, arena) {
+		Ok(id) => id,
+		Err(err) => return ParseResult::Fail(err)
+	};
+
+	// We expect the colon
+	match tg.next() {
+		Some(&Token::Colon) => (),
+		Some(op) => return ParseResult::Fail(Error::new(ErrorKind::MissingColon,
+			tg.span(), format!("expected ':', found: {}", op.to_string()))),
+		None => return ParseResult::Fail(Error::new(ErrorKind::MissingColon,
+			tg.end_span(), "missing ':'"))
+	}
+	let else_id = match parse_branch(tg, arena) {
+		Ok(id) => id,
+		Err(err) => return ParseResult::Fail(err)
+	};
+	ParseResult::Some(alloc_conditional(arena, cond_id, then_id, else_id, question_span))
+}
+
+// creates the evaluation tree from the list of tokens of the input,
+// params being those of the function being defined if any
+fn make_tree(input: &str, tokens: &[TokenSpan], params: &[String], registry: &funcs::Registry,
+	settings: &Settings) -> Result<Tree, Error> {
 	let mut arena = TreeArena::new_with_size(tokens.len());
-	let mut tg = TokenGetter { tokens: &mut tokens, index: 0 };
+	let mut tg = TokenGetter { input, tokens: &tokens, registry, params, index: 0, implicit_mul: settings.implicit_mul };
 	let root_id = match parse_expression(&mut tg, &mut arena) {
-		ParseResult::None => arena.alloc_leaf(Token::Nothing),
+		ParseResult::None => arena.alloc_leaf(Token::Nothing, Span::at(0)),
 		ParseResult::Some(root_id) => root_id,
 		ParseResult::Fail(err) => return Err(err),
 	};
+	// Everything must be used
+	if let Some(op) = tg.next() {
+		return Err(Error::new(ErrorKind::UnexpectedToken,
+			tg.span(), format!("unexpected {}", op.to_string())));
+	}
 	let tree = Tree { arena: arena, root_id: root_id };
 	Ok(tree)
 }
 
+// Parses an input like "f(x, y) = x*y + 1". Returns None if the input
+// is not a function definition.
+pub fn parse_definition(input: &str, settings: &Settings, registry: &funcs::Registry)
+	-> Result<Option<UserFunc>, Error> {
+	let tokens = try!(token::tokenize_sep(input, settings.number_style.separators()));
+	let assign_index = match tokens.iter().position(|ts| match ts.token { Token::Assign => true, _ => false }) {
+		Some(index) => index,
+		None => return Ok(None)
+	};
+	let bad_definition = |span: Span| {
+		Err(Error::new(ErrorKind::BadDefinition, span, "expected a definition like f(x) = x + 1"))
+	};
+
+	// Name and parameters, before the '='
+	let name = match tokens[0].token {
+		Token::Func(name) => name.to_string(),
+		_ => return bad_definition(tokens[0].span)
+	};
+	if registry.get(&name).is_some() {
+		return Err(Error::new(ErrorKind::BadDefinition, tokens[0].span,
+			format!("{} is a built-in function", name)));
+	}
+	let mut params: Vec<String> = Vec::new();
+	let mut index = 1;
+	while index < assign_index {
+		let ts = tokens[index];
+		let expected_param = index % 2 == 1;
+		match ts.token {
+			Token::Var(param) if expected_param => {
+				if params.contains(&param.to_string()) {
+					return Err(Error::new(ErrorKind::BadDefinition, ts.span,
+						format!("parameter {} appears twice", param.to_string())));
+				}
+				params.push(param.to_string());
+			},
+			Token::Comma if !expected_param => (),
+			Token::ParClose if !expected_param && index + 1 == assign_index => (),
+			_ => return bad_definition(ts.span)
+		}
+		index += 1;
+	}
+	let par_closed = match tokens[assign_index - 1].token { Token::ParClose => true, _ => false };
+	if params.is_empty() || !par_closed {
+		return bad_definition(tokens[assign_index].span);
+	}
+
+	// Body, after the '='
+	let body = &tokens[assign_index + 1..];
+	if body.is_empty() {
+		return Err(Error::new(ErrorKind::BadDefinition, Span::at(tokens[assign_index].span.end),
+			"missing function body"));
+	}
+	let tree = try!(make_tree(input, body, &params, registry, settings));
+	if let Some(span) = tree.find_unknown_var(&params) {
+		let var_name = input.chars().skip(span.start).take(span.end - span.start).collect::<String>();
+		return Err(Error::new(ErrorKind::UnknownVar, span, format!("unknown variable: {}", var_name)));
+	}
+	Ok(Some(UserFunc { name, params, text: input.trim().to_string(), tree }))
+}
+
+// Evaluates the input, returns the value rounded as the settings
+// say, or what prevented to get it
+pub fn eval_debug(input: &str, settings: &Settings, registry: &funcs::Registry, debug: bool)
+	-> Result<Quantity, Error> {
+	eval_value(input, settings, registry, debug).map(|(nv, _)| nv)
+}
+
+// Same as eval_debug(), also giving a text for the state label: true or
+// false when the last operator is a comparison, or what the function
+// called last has to say about the value
+fn eval_value(input: &str, settings: &Settings, registry: &funcs::Registry, debug: bool)
+	-> Result<(Quantity, Option<String>), Error> {
+	let tokens = try!(token::tokenize_sep(input, settings.number_style.separators()));
+	let tree = try!(make_tree(input, &tokens, &[], registry, settings));
+	if debug {
+		println!("{}", tree.to_string());
+	}
+	let quantity = try!(tree.eval(settings, registry));
+	let nv = quantity.value;
+	let root_token = tree.get_node(tree.root_id).token;
+	let state = match root_token {
+		Token::Func(name) => registry.get(name.as_str()).and_then(|func| func.describe(nv)),
+		_ if root_token.is_boolean() => Some(if nv.is_zero() { "false" } else { "true" }.to_string()),
+		_ => None
+	};
+
+	let whole_input = Span::new(0, input.chars().count());
+	let rounded = match BigDec::round(nv, settings.frac_digits, settings.rounding) {
+		Ok(rounded) => rounded,
+		Err(err) => return Err(Error::from_big_dec(err, whole_input))
+	};
+	if rounded.int_digit_count() > settings.int_digits {
+		return Err(Error::new(ErrorKind::Overflow, whole_input, "overflow"));
+	}
+	Ok((Quantity { value: rounded, ..quantity }, state))
+}
+
 #[allow(dead_code)]
 pub fn eval_input(input: &str) -> String {
-	eval_input_debug(input, false)
+	eval_input_debug(input, &Settings::new(), &funcs::Registry::new(), false)
 }
 
-pub fn eval_input_debug(input: &str, debug: bool) -> String {
-	let tokens_res = token::tokenize(input);
-	let tokens = match tokens_res {
-		Ok(tokens) => tokens,
-		Err(err) => { return err.to_string(); }
-	};
+pub fn eval_input_debug(input: &str, settings: &Settings, registry: &funcs::Registry, debug: bool) -> String {
+	match eval_debug(input, settings, registry, debug) {
+		Ok(nv) => nv.to_string(),
+		Err(err) => err.to_string()
+	}
+}
 
-	match make_tree(tokens) {
-		Ok(tree) => {
-			if debug {
-				println!("{}", tree.to_string());
-			}
-			match tree.eval() {
-				Ok(nv) => {
-					//println!("dbg: {:?}", nv);
-					nv.to_string()
-				},
-				Err(err) => err.to_string()
-			}
-		},
-		Err(err) => err.into()
+// Text of a result that reads back as the same value with these
+// settings, such as 1,5 km with a decimal comma. Digits are not grouped.
+pub fn format_result(quantity: Quantity, settings: &Settings) -> String {
+	let text = quantity.to_string();
+	// The dots are the decimal points of the value or of the seconds of
+	// a duration
+	match settings.number_style.separators().decimal {
+		'.' => text,
+		decimal => text.replace('.', &decimal.to_string())
 	}
 }
 
+// Decimal text of the value, with a magnitude suffix like 1.5M when the
+// settings ask for it and the value has no unit
+fn format_result_dec(quantity: Quantity, settings: &Settings) -> String {
+	let separators = settings.number_style.separators();
+	let group = settings.group_digits;
+	let suffixed = match units::best_magnitude(quantity.value) {
+		Some(best) if settings.magnitude_suffix && quantity.unit.is_none() => best,
+		_ => return format::format_dec(&quantity.value.to_string(), separators, group)
+	};
+	let (quotient, suffix) = suffixed;
+	let rounded = BigDec::round(quotient, settings.frac_digits, settings.rounding).unwrap_or(quotient);
+	format!("{}{}", format::format_dec(&rounded.to_string(), separators, group), suffix)
+}
+
 pub struct DetailedEval {
 	pub state_str: String,
 	pub result_dec: String,
-	pub result_hex: String
+	pub result_hex: String,
+	pub result_bin: String, // only for integers
+	pub result_char: String, // only for non-negative integers
+	pub value: Option<BigDec>,
+	pub error: Option<Error>
 }
 
-pub fn eval_input_debug_detailed(input: &str, debug: bool) -> DetailedEval {
+pub fn eval_input_debug_detailed(input: &str, settings: &Settings, registry: &funcs::Registry, debug: bool)
+	-> DetailedEval {
 	let mut ret = DetailedEval {
 		state_str: "".into(),
 		result_dec: "--".into(),
-		result_hex: "--".into()
+		result_hex: "--".into(),
+		result_bin: "--".into(),
+		result_char: "--".into(),
+		value: None,
+		error: None
 	};
-	let tokens_res = token::tokenize(input);
-	let tokens = match tokens_res {
-		Ok(tokens) => tokens,
-		Err(err) => { ret.state_str = err.to_string(); return ret; }
-	};
-
-	match make_tree(tokens) {
-		Ok(tree) => {
-			if debug {
-				println!("{}", tree.to_string());
-			}
-			match tree.eval() {
-				Ok(nv) => {
-					//println!("dbg: {:?}", nv);
-					ret.result_dec = nv.to_string();
-					ret.result_hex = nv.to_string_hex(8);
-					ret
-				},
-				Err(err) => {
-					ret.state_str = err.to_string(); ret
+	match parse_definition(input, settings, registry) {
+		Ok(Some(func)) => {
+			ret.state_str = format!("Enter defines {}", func.signature());
+			return ret;
+		},
+		Ok(None) => (),
+		Err(err) => {
+			ret.state_str = err.to_string();
+			ret.error = Some(err);
+			return ret;
+		}
+	}
+	match eval_value(input, settings, registry, debug) {
+		Ok((quantity, state)) => {
+			ret.state_str = state.unwrap_or_default();
+			let nv = quantity.value;
+			let digit_count = (settings.word_size / 4) as i32;
+			let group = settings.group_digits;
+			ret.result_dec = match quantity.unit {
+				Some(unit) if unit.is_formatted() => quantity.to_string(),
+				Some(unit) => format!("{} {}", format_result_dec(quantity, settings), unit.name()),
+				None => format_result_dec(quantity, settings)
+			};
+			ret.result_hex = format::format_prefixed(&nv.to_string_hex(digit_count), group);
+			if nv.is_integer() {
+				ret.result_bin = format::format_prefixed(&nv.to_string_bin(digit_count), group);
+				let code = nv.to_i128();
+				if code >= 0 && code <= u64::max_value() as i128 {
+					ret.result_char = format::format_char(code as u64);
 				}
 			}
+			ret.value = Some(nv);
 		},
 		Err(err) => {
-			ret.state_str = err.into(); ret
+			ret.state_str = err.to_string();
+			ret.error = Some(err);
 		}
 	}
+	ret
 }
 
 #[test]
@@ -505,9 +1204,338 @@ fn test_eval() {
 	assert_eq!("2", eval_input("7%5"));
 	assert_eq!("-2", eval_input("-3+1"));
 	assert_eq!("720", eval_input("6!"));
+	assert_eq!("48", eval_input("6!!"));
+	assert_eq!("105", eval_input("7!!"));
+	assert_eq!("8", eval_input("2 + 3!"));
+	assert_eq!("factorial needs a non-negative integer", eval_input("(-3)!"));
+	assert_eq!("overflow", eval_input("1000!"));
+	assert_eq!("3.32335097044784", &eval_input("2.5!")[..16]);
 }
 
 #[test]
 fn test_order() {
 	assert_eq!("0", eval_input("4+2-3-3"));
 }
+
+#[test]
+fn test_error_span() {
+	let err = eval_debug_default("1 + 2/0", false).err().unwrap();
+	assert_eq!(ErrorKind::DivideByZero, err.kind());
+	assert_eq!(Span::new(5, 6), err.span());
+	assert_eq!("divide by zero", err.to_string());
+
+	let err = eval_debug_default("(1+2", false).err().unwrap();
+	assert_eq!(ErrorKind::MissingParClose, err.kind());
+	assert_eq!(Span::at(4), err.span());
+
+	let err = eval_debug_default("3 * )", false).err().unwrap();
+	assert_eq!(ErrorKind::UnexpectedToken, err.kind());
+	assert_eq!(Span::new(4, 5), err.span());
+
+	let err = eval_debug_default("2 + foo(1)", false).err().unwrap();
+	assert_eq!(ErrorKind::UnknownFunc, err.kind());
+	assert_eq!(Span::new(4, 8), err.span());
+}
+
+#[cfg(test)]
+fn eval_debug_default(input: &str, debug: bool) -> Result<Quantity, Error> {
+	eval_debug(input, &Settings::new(), &funcs::Registry::new(), debug)
+}
+
+#[test]
+fn test_settings() {
+	use format::NumberStyle;
+	let mut settings = Settings::new();
+	let registry = funcs::Registry::new();
+	settings.frac_digits = 2;
+	assert_eq!("0.67", eval_input_debug("2/3", &settings, &registry, false));
+	settings.int_digits = 3;
+	assert_eq!("overflow", eval_input_debug("999+1", &settings, &registry, false));
+
+	settings.group_digits = true;
+	settings.number_style = NumberStyle::Plain;
+	let de = eval_input_debug_detailed("-1234567.125", &settings, &registry, false);
+	assert_eq!("overflow", de.state_str);
+	settings.int_digits = 20;
+	let de = eval_input_debug_detailed("-1234567.125", &settings, &registry, false);
+	assert_eq!("-1_234_567.13", de.result_dec);
+	assert_eq!("0xFFED_2979", de.result_hex);
+	assert_eq!("--", de.result_bin);
+
+	settings.number_style = NumberStyle::European;
+	let de = eval_input_debug_detailed("1234,5 + 1", &settings, &registry, false);
+	assert_eq!("1.235,5", de.result_dec);
+	assert_eq!("1.234.568,5", eval_input_debug_detailed("1.234.567,5 + 1", &settings, &registry, false).result_dec);
+	assert_eq!("1.235,5", eval_input_debug_detailed("1.234,5 + 1", &settings, &registry, false).result_dec);
+	assert_eq!("1,5", eval_input_debug_detailed("sqrt(2,25)", &settings, &registry, false).result_dec);
+	assert_eq!("4,5", eval_input_debug_detailed("max(2,5; 4,5)", &settings, &registry, false).result_dec);
+	let de = eval_input_debug_detailed("-2", &settings, &registry, false);
+	assert_eq!("0b1111_1111_1111_1111_1111_1111_1111_1110", de.result_bin);
+
+	settings.number_style = NumberStyle::English;
+	assert_eq!("1235.5", eval_input_debug("1,234.5 + 1", &settings, &registry, false));
+}
+
+#[test]
+fn test_implicit_mul() {
+	let mut settings = Settings::new();
+	settings.frac_digits = 4;
+	let registry = funcs::Registry::new();
+	let eval = |input: &str, settings: &Settings| eval_input_debug(input, settings, &registry, false);
+	assert_eq!("6.2832", eval("2pi", &settings));
+	assert_eq!("27", eval("3(4+5)", &settings));
+	assert_eq!("6", eval("(1+1)(2+1)", &settings));
+	assert_eq!("0.1592", eval("1/2pi", &settings));
+	assert_eq!("10", eval("2sqrt(25)", &settings));
+	assert_eq!("unknown variable: x", eval("2x", &settings));
+	assert_eq!("unexpected 3", eval("(2)3", &settings));
+	assert_eq!("6.2832", eval("pi(2)", &settings));
+	assert_eq!("5.4366", eval("e(1+1)", &settings));
+	assert_eq!("10 km", eval("km(2 * 5)", &settings));
+	assert_eq!("unknown func", eval("nope(2)", &settings));
+	assert_eq!("3", eval("min(3, 4)", &settings));
+	assert_eq!("unexpected )", eval("pi()", &settings));
+
+	settings.implicit_mul = false;
+	assert_eq!("unexpected pi", eval("2pi", &settings));
+	assert_eq!("unexpected (", eval("3(4+5)", &settings));
+	assert_eq!("unknown func", eval("pi(2)", &settings));
+	assert_eq!("3.1416", eval("pi", &settings));
+}
+
+#[test]
+fn test_comparisons() {
+	assert_eq!("1", eval_input("0x8000 > 0x7FFF"));
+	assert_eq!("1", eval_input("(12 & 4) == 4"));
+	assert_eq!("1", eval_input("12 & 4 == 4"));
+	assert_eq!("0", eval_input("-3 >= -2"));
+	assert_eq!("1", eval_input("-3 < 2 && 2 <= 2"));
+	assert_eq!("1", eval_input("1 > 2 || 3 != 4"));
+	assert_eq!("0", eval_input("1 + 1 == 3"));
+	assert_eq!("1", eval_input("!(1 > 2)"));
+	assert_eq!("1", eval_input("!!5"));
+	assert_eq!("1", eval_input("3! == 6"));
+	assert_eq!("1", eval_input("!0 + 1 == 2"));
+	assert_eq!("missing operand after <=", eval_input("1 <="));
+
+	let settings = Settings::new();
+	let registry = funcs::Registry::new();
+	let de = eval_input_debug_detailed("2 + 2 == 4", &settings, &registry, false);
+	assert_eq!("true", de.state_str);
+	assert_eq!("1", de.result_dec);
+	let de = eval_input_debug_detailed("!1", &settings, &registry, false);
+	assert_eq!("false", de.state_str);
+	let de = eval_input_debug_detailed("(1 < 2) + 1", &settings, &registry, false);
+	assert_eq!("", de.state_str);
+	let de = eval_input_debug_detailed("factor(2*60)", &settings, &registry, false);
+	assert_eq!("2^3 * 3 * 5", de.state_str);
+	assert_eq!("120", de.result_dec);
+}
+
+#[test]
+fn test_conditionals() {
+	assert_eq!("0", eval_input("0 != 0 ? 1/0 : 0"));
+	assert_eq!("0.5", eval_input("2 != 0 ? 1/2 : 0"));
+	assert_eq!("3", eval_input("if(1 > 2, 1/0, 3)"));
+	assert_eq!("2", eval_input("1 ? 0 ? 1 : 2 : 3"));
+	assert_eq!("11", eval_input("1 + (0 ? 5 : 10)"));
+	assert_eq!("0", eval_input("0 && 1/0"));
+	assert_eq!("1", eval_input("1 || 1/0"));
+	assert_eq!("missing ':'", eval_input("1 ? 2"));
+	assert_eq!("missing operand after :", eval_input("1 ? 2 :"));
+	assert_eq!("if expects 3 arguments, not 2", eval_input("if(1, 2)"));
+
+	// Only the chosen branch recurses
+	let settings = Settings::new();
+	let mut registry = funcs::Registry::new();
+	let func = parse_definition("fact(n) = n <= 1 ? 1 : n * fact(n - 1)", &settings, &registry).ok().unwrap().unwrap();
+	registry.user_funcs.define(func);
+	assert_eq!("3628800", eval_input_debug("fact(10)", &settings, &registry, false));
+}
+
+#[test]
+fn test_units() {
+	assert_eq!("5.3 km", eval_input("5 km + 300 m"));
+	assert_eq!("3072 KiB", eval_input("3 MiB in KiB"));
+	assert_eq!("50 km/h", eval_input("100 km / 2 h"));
+	assert_eq!("25 m/s", eval_input("90 km/h in m/s"));
+	assert_eq!("90 min", eval_input("1.5 h to min"));
+	assert_eq!("32 degF", eval_input("0 degC in degF"));
+	assert_eq!("1000", eval_input("1 km / 1 m"));
+	assert_eq!("2.4 GHz", eval_input("2400 MHz in GHz"));
+	assert_eq!("1", eval_input("1 km > 999 m"));
+	assert_eq!("1", eval_input("0 degC == 32 degF"));
+	assert_eq!("1", eval_input("0 degC > 31 degF"));
+	assert_eq!("24 m^3", eval_input("3 m * 2 m * 4 m"));
+	assert_eq!("incompatible units: km and s", eval_input("2 km + 3 s"));
+	assert_eq!("incompatible units: no unit and m", eval_input("1 + 2 m"));
+	assert_eq!("expected a number without unit, found m", eval_input("sqrt(4 m)"));
+	assert_eq!("missing unit after in", eval_input("3 m in"));
+
+	let mut settings = Settings::new();
+	settings.implicit_mul = false;
+	let mut registry = funcs::Registry::new();
+	let func = parse_definition("area(w, h) = w * h", &settings, &registry).ok().unwrap().unwrap();
+	registry.user_funcs.define(func);
+	assert_eq!("6 m^2", eval_input_debug("area(2 m, 3 m)", &settings, &registry, false));
+	let de = eval_input_debug_detailed("1 KiB + 1 B", &settings, &registry, false);
+	assert_eq!("1.0009765625 KiB", de.result_dec);
+	assert_eq!("1.0009765625", de.value.unwrap().to_string());
+}
+
+#[test]
+fn test_char_literals() {
+	assert_eq!("65", eval_input("'A'"));
+	assert_eq!("10", eval_input("'\\n'"));
+	assert_eq!("9786", eval_input("'\\u{263A}'"));
+	assert_eq!("39", eval_input("'\\''"));
+	assert_eq!("25", eval_input("'Z' - 'A'"));
+	assert_eq!("invalid character literal", eval_input("'AB'"));
+	assert_eq!("invalid character literal", eval_input("''"));
+	assert_eq!("invalid character literal", eval_input("'\\u{110000}'"));
+
+	let settings = Settings::new();
+	let registry = funcs::Registry::new();
+	let de = eval_input_debug_detailed("0x41", &settings, &registry, false);
+	assert_eq!("'A'", de.result_char);
+	let de = eval_input_debug_detailed("0x7F454C46", &settings, &registry, false);
+	assert_eq!("--", de.result_char);
+	let de = eval_input_debug_detailed("0x50494E47", &settings, &registry, false);
+	assert_eq!("\"PING\" BE, \"GNIP\" LE", de.result_char);
+	let de = eval_input_debug_detailed("-1", &settings, &registry, false);
+	assert_eq!("--", de.result_char);
+}
+
+#[test]
+fn test_checksums() {
+	assert_eq!("3421780262", eval_input("crc32(\"123456789\")"));
+	assert_eq!("412718745", eval_input("crc32(0x1234)"));
+	assert_eq!("0", eval_input("crc32(0x12345678) == crc32(0x5678)"));
+	assert_eq!("155881536", eval_input("crc32(0x1234, 2, 1)"));
+	assert_eq!("0", eval_input("crc32(\"\")"));
+	assert_eq!("244", eval_input("crc8(\"123456789\")"));
+	assert_eq!("3826002220", eval_input("fnv1a(\"a\")"));
+	assert_eq!("crc32: the number of bytes must be from 1 to 8", eval_input("crc32(1, 9)"));
+	assert_eq!("sqrt does not accept strings", eval_input("sqrt(\"4\")"));
+	assert_eq!("a string can only be the argument of a checksum function", eval_input("\"abc\" + 1"));
+	assert_eq!("a string can only be the argument of a checksum function", eval_input("crc32(\"a\", 1)"));
+}
+
+#[test]
+fn test_dates() {
+	assert_eq!("9h42m38s", eval_input("1h23m14s * 7"));
+	assert_eq!("2027-01-15", eval_input("2026-10-17 + 90 days"));
+	assert_eq!("2026-10-17T10:00:00", eval_input("2026-10-17T08:30 + 1h30m"));
+	assert_eq!("2026-10-16T23:59:45", eval_input("2026-10-17 - 15 s"));
+	assert_eq!("289d", eval_input("2026-10-17 - 2026-01-01"));
+	assert_eq!("289 day", eval_input("(2026-10-17 - 2026-01-01) in day"));
+	assert_eq!("1", eval_input("2026-10-17 > 2026-01-01"));
+	assert_eq!("1767225600", eval_input("unixtime(2026-01-01)"));
+	assert_eq!("2023-11-14T22:13:20", eval_input("fromunix(1700000000)"));
+	assert_eq!("-1h30m", eval_input("-1h30m"));
+	assert_eq!("0.25s", eval_input("250ms"));
+	assert_eq!("2h", eval_input("2h"));
+	assert_eq!("7200 s", eval_input("2h in s"));
+	assert_eq!("2 h", eval_input("2 h"));
+	assert_eq!("3d12h", eval_input("3.5d"));
+	assert_eq!("1983", eval_input("2010 - 10 - 17"));
+	assert_eq!("invalid date", eval_input("2026-02-30"));
+	assert_eq!("incompatible units: date and date", eval_input("2026-01-01 + 2026-01-01"));
+	assert_eq!("incompatible units: date and no unit", eval_input("2026-01-01 + 3"));
+	assert_eq!("unixtime expects a date", eval_input("unixtime(3)"));
+
+	let settings = Settings::new();
+	let registry = funcs::Registry::new();
+	let de = eval_input_debug_detailed("2h - 30min", &settings, &registry, false);
+	assert_eq!("1h30m", de.result_dec);
+	assert_eq!("30m0s", eval_input("0.5h"));
+	assert_eq!("1800 s", eval_input("30m0s in s"));
+	let de = eval_input_debug_detailed("2h0m - 30 min", &settings, &registry, false);
+	assert_eq!("1h30m", de.result_dec);
+	assert_eq!("5400", de.value.unwrap().to_string());
+}
+
+#[test]
+fn test_magnitude_suffixes() {
+	assert_eq!("4000", eval_input("4k"));
+	assert_eq!("65536", eval_input("64Ki"));
+	assert_eq!("1500000", eval_input("1.5M"));
+	assert_eq!("2147483648", eval_input("2Gi"));
+	assert_eq!("0.00025", eval_input("250u"));
+	assert_eq!("0.005", eval_input("5m"));
+	assert_eq!("-4000", eval_input("-4k"));
+	assert_eq!("unknown variable: k", eval_input("4 k"));
+	assert_eq!("5 m", eval_input("5 m"));
+	assert_eq!("5 km", eval_input("5km"));
+	assert_eq!("3 MiB", eval_input("3MiB"));
+	assert_eq!("6.28318530717958647692", eval_input("2pi"));
+	assert_eq!("overflow", eval_input("100000000P"));
+
+	let mut settings = Settings::new();
+	settings.magnitude_suffix = true;
+	let registry = funcs::Registry::new();
+	let dec = |input: &str, settings: &Settings| eval_input_debug_detailed(input, settings, &registry, false).result_dec;
+	assert_eq!("64Ki", dec("65536", &settings));
+	assert_eq!("1.5M", dec("3M / 2", &settings));
+	assert_eq!("-250m", dec("-0.25", &settings));
+	assert_eq!("12", dec("12", &settings));
+	assert_eq!("1500 m", dec("1.5 km in m", &settings));
+	settings.frac_digits = 2;
+	assert_eq!("1.23k", dec("1234", &settings));
+
+	// Each suffix reads back to the value it was written for
+	settings.frac_digits = Settings::new().frac_digits;
+	for text in &["1.5P", "2T", "3G", "4M", "5k", "250m", "-250m", "25u", "2.5n", "7p", "64Ki", "3Mi", "2Gi", "5Ti", "7Pi"] {
+		let value = eval_input(text);
+		assert_eq!(text.to_string(), dec(&value, &settings));
+		assert_eq!(value, eval_input(&dec(&value, &settings)));
+	}
+}
+
+#[test]
+fn test_user_funcs() {
+	let settings = Settings::new();
+	let mut registry = funcs::Registry::new();
+	for text in &["sq(x) = x*x", "hyp(a, b) = sqrt(sq(a) + sq(b))", "loop(x) = loop(x) + 1", "f(n) = 2n + 1",
+		"g(k) = 3k", "h(m) = 2m + 1k", "twice(h) = 2h", "pron(x) = x(x+1)", "fact(n) = n > 1 ? n fact(n - 1) : 1"] {
+		let func = parse_definition(text, &settings, &registry).ok().unwrap().unwrap();
+		registry.user_funcs.define(func);
+	}
+	let eval = |input: &str| eval_input_debug(input, &settings, &registry, false);
+	assert_eq!("26", eval("sq(5) + 1"));
+	assert_eq!("5", eval("hyp(3, 4)"));
+	assert_eq!("hyp expects 2 arguments, not 1", eval("hyp(3)"));
+	assert_eq!("sq expects 1 argument, not 2", eval("sq(3, 4)"));
+	assert_eq!("unknown variable: x", eval("x + 1"));
+	assert_eq!("11", eval("f(5)"));
+	assert_eq!("6", eval("g(2)"));
+	assert_eq!("1006", eval("h(3)"));
+	assert_eq!("6", eval("twice(3)"));
+	assert_eq!("12", eval("pron(3)"));
+	assert_eq!("120", eval("fact(5)"));
+
+	let err = eval_debug("2 * loop(1)", &settings, &registry, false).err().unwrap();
+	assert_eq!(ErrorKind::TooManyCalls, err.kind());
+	assert_eq!(Span::new(4, 9), err.span());
+}
+
+#[test]
+fn test_parse_definition() {
+	let settings = Settings::new();
+	let registry = funcs::Registry::new();
+	assert!(parse_definition("1 + 2", &settings, &registry).ok().unwrap().is_none());
+	let func = parse_definition(" f(x, y) = x - y ", &settings, &registry).ok().unwrap().unwrap();
+	assert_eq!("f(x, y)", func.signature());
+	assert_eq!("f(x, y) = x - y", func.text);
+
+	let err = |text: &str| parse_definition(text, &settings, &registry).err().unwrap();
+	assert_eq!(ErrorKind::BadDefinition, err("x = 3").kind());
+	assert_eq!(ErrorKind::BadDefinition, err("f(x, ) = 3").kind());
+	assert_eq!(ErrorKind::BadDefinition, err("f(x, x) = x").kind());
+	assert_eq!(ErrorKind::BadDefinition, err("sqrt(x) = x").kind());
+	assert_eq!(ErrorKind::BadDefinition, err("f(x) =").kind());
+	let unknown = err("f(x) = x + y");
+	assert_eq!(ErrorKind::UnknownVar, unknown.kind());
+	assert_eq!(Span::new(11, 12), unknown.span());
+	assert_eq!(ErrorKind::UnexpectedToken, err("f(x) = x 2").kind());
+}
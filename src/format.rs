@@ -0,0 +1,188 @@
+use std;
+use std::env;
+
+// Characters used to write numbers
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Separators {
+	pub decimal: char,
+	pub group: char
+}
+
+// How decimal results are written. Hexadecimal and binary results
+// are always grouped with '_'.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum NumberStyle {
+	Locale,   // from LC_ALL, LC_NUMERIC or LANG
+	Plain,    // 1_234_567.5
+	English,  // 1,234,567.5
+	European, // 1.234.567,5
+	French,   // 1 234 567,5 with narrow no-break spaces
+	Swiss     // 1'234'567.5
+}
+
+impl NumberStyle {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			NumberStyle::Locale => "locale",
+			NumberStyle::Plain => "plain",
+			NumberStyle::English => "english",
+			NumberStyle::European => "european",
+			NumberStyle::French => "french",
+			NumberStyle::Swiss => "swiss"
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<NumberStyle> {
+		let styles = [NumberStyle::Locale, NumberStyle::Plain, NumberStyle::English,
+			NumberStyle::European, NumberStyle::French, NumberStyle::Swiss];
+		styles.iter().find(|style| style.name() == name).cloned()
+	}
+
+	pub fn separators(&self) -> Separators {
+		match *self {
+			NumberStyle::Locale => locale_style().separators(),
+			NumberStyle::Plain => Separators { decimal: '.', group: '_' },
+			NumberStyle::English => Separators { decimal: '.', group: ',' },
+			NumberStyle::European => Separators { decimal: ',', group: '.' },
+			NumberStyle::French => Separators { decimal: ',', group: '\u{202f}' },
+			NumberStyle::Swiss => Separators { decimal: '.', group: '\'' }
+		}
+	}
+}
+
+// Style matching a locale name such as "fr_FR.UTF-8"
+pub fn style_from_locale_name(locale: &str) -> NumberStyle {
+	let locale = locale.split(|c| c == '.' || c == '@').next().unwrap_or("");
+	let mut parts = locale.split('_');
+	let lang = parts.next().unwrap_or("");
+	let country = parts.next().unwrap_or("");
+
+	if country == "CH" && (lang == "de" || lang == "fr" || lang == "it") {
+		return NumberStyle::Swiss;
+	}
+	match lang {
+		"" | "C" | "POSIX" => NumberStyle::Plain,
+		"fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "fi" | "sv" | "nb" | "nn" | "no"
+			| "hu" | "bg" | "lt" | "lv" | "et" => NumberStyle::French,
+		"de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr"
+			| "sl" | "sr" | "vi" => NumberStyle::European,
+		_ => NumberStyle::English
+	}
+}
+
+fn locale_style() -> NumberStyle {
+	for var in &["LC_ALL", "LC_NUMERIC", "LANG"] {
+		if let Ok(locale) = env::var(var) {
+			if !locale.is_empty() {
+				return style_from_locale_name(&locale);
+			}
+		}
+	}
+	NumberStyle::Plain
+}
+
+// Inserts sep between groups of group_size digits of the integer part.
+// The text may start with a '-' sign and end with a fractional part.
+pub fn group_int_digits(text: &str, group_size: usize, sep: char) -> String {
+	let (sign, unsigned) = if text.starts_with('-') { ("-", &text[1..]) } else { ("", text) };
+	let int_len = unsigned.find('.').unwrap_or(unsigned.len());
+	let mut ret = String::from(sign);
+	for (i, c) in unsigned[..int_len].chars().enumerate() {
+		if i > 0 && (int_len - i) % group_size == 0 {
+			ret.push(sep);
+		}
+		ret.push(c);
+	}
+	ret.push_str(&unsigned[int_len..]);
+	ret
+}
+
+// Formats the text of a decimal number, as given by BigDec::to_string()
+pub fn format_dec(text: &str, separators: Separators, group: bool) -> String {
+	let mut parts = text.splitn(2, '.');
+	let int_part = parts.next().unwrap_or("");
+	let mut ret = if group {
+		group_int_digits(int_part, 3, separators.group)
+	}
+	else {
+		int_part.to_string()
+	};
+	if let Some(frac_part) = parts.next() {
+		ret.push(separators.decimal);
+		ret.push_str(frac_part);
+	}
+	ret
+}
+
+// Formats the text of a number with a radix prefix such as "0x"
+pub fn format_prefixed(text: &str, group: bool) -> String {
+	if !group || text.len() < 2 {
+		return text.to_string();
+	}
+	format!("{}{}", &text[0..2], group_int_digits(&text[2..], 4, '_'))
+}
+
+// The character of a code point, and the text of its bytes in big and
+// little endian order when they are all printable ASCII, such as 'A' for
+// 0x41 or "AB" BE, "BA" LE for 0x4142. "--" when nothing is printable.
+pub fn format_char(code: u64) -> String {
+	let mut parts = Vec::new();
+	if code <= 0x10ffff {
+		match std::char::from_u32(code as u32) {
+			Some(c) if !c.is_control() => parts.push(format!("'{}'", c)),
+			_ => ()
+		}
+	}
+	let bytes: Vec<u8> = (0..8).rev().map(|i| (code >> (i * 8)) as u8).skip_while(|&b| b == 0).collect();
+	if bytes.len() >= 2 && bytes.iter().all(|&b| b >= 0x20 && b < 0x7f) {
+		let big_endian: String = bytes.iter().map(|&b| b as char).collect();
+		let little_endian: String = big_endian.chars().rev().collect();
+		parts.push(format!("\"{}\" BE, \"{}\" LE", big_endian, little_endian));
+	}
+	if parts.is_empty() {
+		return "--".to_string();
+	}
+	parts.join("  ")
+}
+
+#[test]
+fn test_group_int_digits() {
+	assert_eq!("1", group_int_digits("1", 3, ','));
+	assert_eq!("123", group_int_digits("123", 3, ','));
+	assert_eq!("1,234", group_int_digits("1234", 3, ','));
+	assert_eq!("-123,456.7891", group_int_digits("-123456.7891", 3, ','));
+	assert_eq!("F_FFFF", group_int_digits("FFFFF", 4, '_'));
+}
+
+#[test]
+fn test_format_dec() {
+	let european = NumberStyle::European.separators();
+	assert_eq!("-1.234.567,125", format_dec("-1234567.125", european, true));
+	assert_eq!("1234567,125", format_dec("1234567.125", european, false));
+	let french = NumberStyle::French.separators();
+	assert_eq!("12\u{202f}345", format_dec("12345", french, true));
+	assert_eq!("0x1_0000", format_prefixed("0x10000", true));
+	assert_eq!("0b1010", format_prefixed("0b1010", true));
+}
+
+#[test]
+fn test_format_char() {
+	assert_eq!("'A'", format_char(0x41));
+	assert_eq!("'\u{263a}'  \"&:\" BE, \":&\" LE", format_char(0x263a));
+	assert_eq!("--", format_char(10));
+	assert_eq!("--", format_char(0x110000));
+	assert_eq!("'\u{4142}'  \"AB\" BE, \"BA\" LE", format_char(0x4142));
+	assert_eq!("\"ELF\" BE, \"FLE\" LE", format_char(0x454c46));
+	assert_eq!("--", format_char(0xd800));
+}
+
+#[test]
+fn test_style_from_locale_name() {
+	assert_eq!(NumberStyle::French, style_from_locale_name("fr_FR.UTF-8"));
+	assert_eq!(NumberStyle::Swiss, style_from_locale_name("de_CH.UTF-8"));
+	assert_eq!(NumberStyle::European, style_from_locale_name("de_DE@euro"));
+	assert_eq!(NumberStyle::English, style_from_locale_name("en_US.UTF-8"));
+	assert_eq!(NumberStyle::Plain, style_from_locale_name("C"));
+	assert_eq!(Some(NumberStyle::Swiss), NumberStyle::from_name("swiss"));
+	assert_eq!(None, NumberStyle::from_name("klingon"));
+}
@@ -1,56 +1,483 @@
+use std;
 use big_dec;
 use big_dec::BigDec;
-use token;
+use settings::{AngleUnit, Settings};
+use user_funcs::UserFuncs;
+use units::Quantity;
+use checksums;
+use dates;
 
 pub enum Error {
 	CallFailed(big_dec::Error),
-	UnknownFunc
+	UnknownFunc,
+	WrongArgCount { name: String, min: usize, max: usize, count: usize },
+	NotInteger(String), // function name
+	OutOfDomain(String) // explanation
 }
 
 impl Error {
 	pub fn to_string(&self) -> String {
 		match *self {
 			Error::CallFailed(ref bd_err) => bd_err.to_string(),
-			Error::UnknownFunc => "unknown func".to_string()
+			Error::UnknownFunc => "unknown func".to_string(),
+			Error::WrongArgCount { ref name, min, max, count } => {
+				let expected = if min == max {
+					format!("{} argument{}", min, if min > 1 { "s" } else { "" })
+				}
+				else if max == VARIADIC {
+					format!("at least {} argument{}", min, if min > 1 { "s" } else { "" })
+				}
+				else {
+					format!("{} to {} arguments", min, max)
+				};
+				format!("{} expects {}, not {}", name, expected, count)
+			},
+			Error::NotInteger(ref name) => format!("{} only works with integers", name),
+			Error::OutOfDomain(ref msg) => msg.clone()
 		}
 	}
 }
 
-struct FuncDisp {
+// Maximum number of arguments of functions that accept any number of them
+pub const VARIADIC: usize = std::usize::MAX;
+
+// A function that can be called from an expression. The built-in
+// functions implement it, and other ones can be added to a Registry.
+pub trait Function {
+	fn name(&self) -> &str;
+
+	// Minimum and maximum number of arguments, max may be VARIADIC
+	fn arity(&self) -> (usize, usize);
+
+	// One line description, such as "sqrt(x): square root"
+	fn help(&self) -> &str;
+
+	// Arguments with a fractional part are rejected before the call
+	fn integer_only(&self) -> bool {
+		false
+	}
+
+	// Checks the arguments before the call, returns why they are
+	// not valid if so
+	fn check_domain(&self, _args: &[BigDec]) -> Result<(), String> {
+		Ok(())
+	}
+
+	fn call(&self, args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error>;
+
+	// Call with the bytes of a string literal, such as crc32("abc").
+	// None if the function only takes numbers.
+	fn call_bytes(&self, _bytes: &[u8]) -> Option<Result<BigDec, big_dec::Error>> {
+		None
+	}
+
+	// Text for the state label when the expression is a call of this
+	// function, given the value the call returned
+	fn describe(&self, _result: BigDec) -> Option<String> {
+		None
+	}
+
+	// Call with quantities, for the functions that take or give dates
+	// such as fromunix(). None if the function only takes numbers.
+	fn call_quantities(&self, _args: &[Quantity]) -> Option<Result<Quantity, Error>> {
+		None
+	}
+}
+
+// Function described by a table entry
+#[derive(Clone)]
+struct Builtin {
 	name: &'static str,
-	bdf: fn(arg: BigDec) -> Result<BigDec, big_dec::Error>
-}
-
-pub fn eval_func(name: token::Name, arg: big_dec::BigDec) -> Result<BigDec, Error> {
-	let func_disps = [
-		FuncDisp { name: "zero", bdf: bd_zero },
-		FuncDisp { name: "same", bdf: bd_same },
-		FuncDisp { name: "sqrt", bdf: bd_sqrt },
-		FuncDisp { name: "cos", bdf: bd_cos },
-	];
-	let name_str = name.to_string();
-	for fd in &func_disps {
-		if name_str == fd.name {
-			match (fd.bdf)(arg) {
-				Ok(val) => return Ok(val),
-				Err(err) => return Err(Error::CallFailed(err))
+	min_args: usize,
+	max_args: usize,
+	help: &'static str,
+	integer_only: bool,
+	domain: fn(args: &[BigDec]) -> Result<(), String>,
+	bdf: fn(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error>
+}
+
+impl Function for Builtin {
+	fn name(&self) -> &str {
+		self.name
+	}
+
+	fn arity(&self) -> (usize, usize) {
+		(self.min_args, self.max_args)
+	}
+
+	fn help(&self) -> &str {
+		self.help
+	}
+
+	fn integer_only(&self) -> bool {
+		self.integer_only
+	}
+
+	fn check_domain(&self, args: &[BigDec]) -> Result<(), String> {
+		(self.domain)(args)
+	}
+
+	fn call(&self, args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+		(self.bdf)(args, settings)
+	}
+}
+
+fn any_domain(_: &[BigDec]) -> Result<(), String> {
+	Ok(())
+}
+
+fn non_negative(args: &[BigDec]) -> Result<(), String> {
+	if args.iter().any(|arg| arg.is_negative()) {
+		return Err("the argument must not be negative".to_string());
+	}
+	Ok(())
+}
+
+// Rejects zero and negative arguments, msg being what to say then
+fn check_positive(args: &[BigDec], msg: &str) -> Result<(), String> {
+	if args.iter().any(|arg| arg.is_negative() || arg.is_zero()) {
+		return Err(msg.to_string());
+	}
+	Ok(())
+}
+
+fn positive(args: &[BigDec]) -> Result<(), String> {
+	check_positive(args, "the argument must be positive")
+}
+
+// Lists of values, such as for geomean(a, b, ...)
+fn positive_values(args: &[BigDec]) -> Result<(), String> {
+	check_positive(args, "the values must be positive")
+}
+
+// modpow(b, e, m)
+fn modpow_domain(args: &[BigDec]) -> Result<(), String> {
+	if args[1].is_negative() {
+		return Err("the exponent must not be negative".to_string());
+	}
+	modulus_domain(&args[2])
+}
+
+// modinv(a, m)
+fn modinv_domain(args: &[BigDec]) -> Result<(), String> {
+	try!(modulus_domain(&args[1]));
+	let (a, m) = (args[0].to_i128(), args[1].to_i128());
+	if gcd_u(a.rem_euclid(m) as u128, m as u128) != 1 {
+		return Err(format!("{} has no inverse modulo {}", a, m));
+	}
+	Ok(())
+}
+
+// binomial(x, k). Without an integer x, the product has k factors, so
+// k is kept small.
+fn binomial_domain(args: &[BigDec]) -> Result<(), String> {
+	if !args[1].is_integer() || args[1].is_negative() {
+		return Err("k must be a non-negative integer".to_string());
+	}
+	if !args[0].is_integer() && args[1].to_i128() > 1000 {
+		return Err("k must be at most 1000 when x is not an integer".to_string());
+	}
+	Ok(())
+}
+
+fn gamma_domain(args: &[BigDec]) -> Result<(), String> {
+	if args[0].is_integer() && (args[0].is_negative() || args[0].is_zero()) {
+		return Err("not defined for 0 and negative integers".to_string());
+	}
+	Ok(())
+}
+
+// Values that bit functions accept: 64-bit words, signed or not
+fn is_word(arg: &BigDec) -> bool {
+	let val = arg.to_i128();
+	val >= std::i64::MIN as i128 && val <= std::u64::MAX as i128
+}
+
+fn check_width(width: Option<&BigDec>) -> Result<(), String> {
+	match width.map(|width| width.to_i128()) {
+		Some(width) if width < 1 || width > 64 => Err("the width must be from 1 to 64".to_string()),
+		_ => Ok(())
+	}
+}
+
+// f(x) or f(x, width)
+fn word_domain(args: &[BigDec]) -> Result<(), String> {
+	if !is_word(&args[0]) {
+		return Err("the argument must fit in 64 bits".to_string());
+	}
+	check_width(args.get(1))
+}
+
+// rotl(x, n) or rotl(x, n, width)
+fn rotate_domain(args: &[BigDec]) -> Result<(), String> {
+	try!(word_domain(&args[0..1]));
+	check_width(args.get(2))
+}
+
+fn bit_domain(args: &[BigDec]) -> Result<(), String> {
+	if args.iter().any(|arg| arg.is_negative() || arg.to_i128() > 63) {
+		return Err("bit positions go from 0 to 63".to_string());
+	}
+	Ok(())
+}
+
+// mask(hi, lo)
+fn mask_domain(args: &[BigDec]) -> Result<(), String> {
+	try!(bit_domain(args));
+	if args[0].to_i128() < args[1].to_i128() {
+		return Err("hi must not be below lo".to_string());
+	}
+	Ok(())
+}
+
+// field(x, hi, lo)
+fn field_domain(args: &[BigDec]) -> Result<(), String> {
+	try!(word_domain(&args[0..1]));
+	mask_domain(&args[1..])
+}
+
+fn modulus_domain(m: &BigDec) -> Result<(), String> {
+	if m.is_negative() || m.is_zero() {
+		return Err("the modulus must be positive".to_string());
+	}
+	Ok(())
+}
+
+const BUILTINS: [Builtin; 40] = [
+	Builtin { name: "zero", min_args: 1, max_args: 1, help: "zero(x): 0",
+		integer_only: false, domain: any_domain, bdf: bd_zero },
+	Builtin { name: "same", min_args: 1, max_args: 1, help: "same(x): x",
+		integer_only: false, domain: any_domain, bdf: bd_same },
+	Builtin { name: "sqrt", min_args: 1, max_args: 1, help: "sqrt(x): square root",
+		integer_only: false, domain: non_negative, bdf: bd_sqrt },
+	Builtin { name: "cos", min_args: 1, max_args: 1, help: "cos(x): cosine",
+		integer_only: false, domain: any_domain, bdf: bd_cos },
+	// Expressions evaluate only the chosen argument, see eval.rs
+	Builtin { name: "if", min_args: 3, max_args: 3, help: "if(c, a, b): a if c is not 0, b otherwise",
+		integer_only: false, domain: any_domain, bdf: bd_if },
+	Builtin { name: "gcd", min_args: 2, max_args: VARIADIC, help: "gcd(a, b, ...): greatest common divisor",
+		integer_only: true, domain: any_domain, bdf: bd_gcd },
+	Builtin { name: "lcm", min_args: 2, max_args: VARIADIC, help: "lcm(a, b, ...): least common multiple",
+		integer_only: true, domain: any_domain, bdf: bd_lcm },
+	Builtin { name: "modpow", min_args: 3, max_args: 3, help: "modpow(b, e, m): b^e modulo m",
+		integer_only: true, domain: modpow_domain, bdf: bd_modpow },
+	Builtin { name: "modinv", min_args: 2, max_args: 2, help: "modinv(a, m): x such that a*x modulo m is 1",
+		integer_only: true, domain: modinv_domain, bdf: bd_modinv },
+	Builtin { name: "isprime", min_args: 1, max_args: 1, help: "isprime(n): 1 if n is prime, 0 otherwise",
+		integer_only: true, domain: any_domain, bdf: bd_isprime },
+	Builtin { name: "ncr", min_args: 2, max_args: 2, help: "ncr(n, k): ways to choose k items among n",
+		integer_only: true, domain: non_negative, bdf: bd_ncr },
+	Builtin { name: "npr", min_args: 2, max_args: 2, help: "npr(n, k): ways to arrange k items among n",
+		integer_only: true, domain: non_negative, bdf: bd_npr },
+	Builtin { name: "binomial", min_args: 2, max_args: 2,
+		help: "binomial(x, k): binomial coefficient, x may be any number",
+		integer_only: false, domain: binomial_domain, bdf: bd_binomial },
+	Builtin { name: "fib", min_args: 1, max_args: 1, help: "fib(n): n-th Fibonacci number",
+		integer_only: true, domain: non_negative, bdf: bd_fib },
+	Builtin { name: "gamma", min_args: 1, max_args: 1, help: "gamma(x): gamma function, gamma(n) is (n-1)!",
+		integer_only: false, domain: gamma_domain, bdf: bd_gamma },
+	// Bit functions, the width defaults to the word size of the settings
+	Builtin { name: "bswap16", min_args: 1, max_args: 1, help: "bswap16(x): x with its 2 low bytes swapped",
+		integer_only: true, domain: word_domain, bdf: bd_bswap16 },
+	Builtin { name: "bswap32", min_args: 1, max_args: 1, help: "bswap32(x): x with its 4 low bytes reversed",
+		integer_only: true, domain: word_domain, bdf: bd_bswap32 },
+	Builtin { name: "bswap64", min_args: 1, max_args: 1, help: "bswap64(x): x with its 8 bytes reversed",
+		integer_only: true, domain: word_domain, bdf: bd_bswap64 },
+	Builtin { name: "popcount", min_args: 1, max_args: 2, help: "popcount(x, width): number of bits set",
+		integer_only: true, domain: word_domain, bdf: bd_popcount },
+	Builtin { name: "clz", min_args: 1, max_args: 2, help: "clz(x, width): number of leading zero bits",
+		integer_only: true, domain: word_domain, bdf: bd_clz },
+	Builtin { name: "ctz", min_args: 1, max_args: 2, help: "ctz(x, width): number of trailing zero bits",
+		integer_only: true, domain: word_domain, bdf: bd_ctz },
+	Builtin { name: "bitrev", min_args: 1, max_args: 2, help: "bitrev(x, width): x with its bits reversed",
+		integer_only: true, domain: word_domain, bdf: bd_bitrev },
+	Builtin { name: "rotl", min_args: 2, max_args: 3, help: "rotl(x, n, width): x rotated left by n bits",
+		integer_only: true, domain: rotate_domain, bdf: bd_rotl },
+	Builtin { name: "rotr", min_args: 2, max_args: 3, help: "rotr(x, n, width): x rotated right by n bits",
+		integer_only: true, domain: rotate_domain, bdf: bd_rotr },
+	Builtin { name: "bit", min_args: 1, max_args: 1, help: "bit(n): 2^n, only bit n set",
+		integer_only: true, domain: bit_domain, bdf: bd_bit },
+	Builtin { name: "mask", min_args: 2, max_args: 2, help: "mask(hi, lo): bits lo to hi set",
+		integer_only: true, domain: mask_domain, bdf: bd_mask },
+	Builtin { name: "field", min_args: 3, max_args: 3, help: "field(x, hi, lo): bits lo to hi of x",
+		integer_only: true, domain: field_domain, bdf: bd_field },
+	Builtin { name: "sext", min_args: 2, max_args: 2, help: "sext(x, bits): x sign extended from its low bits",
+		integer_only: true, domain: word_domain, bdf: bd_sext },
+	Builtin { name: "sum", min_args: 1, max_args: VARIADIC, help: "sum(a, b, ...): sum of the values",
+		integer_only: false, domain: any_domain, bdf: bd_sum },
+	Builtin { name: "avg", min_args: 1, max_args: VARIADIC, help: "avg(a, b, ...): arithmetic mean",
+		integer_only: false, domain: any_domain, bdf: bd_mean },
+	Builtin { name: "mean", min_args: 1, max_args: VARIADIC, help: "mean(a, b, ...): arithmetic mean",
+		integer_only: false, domain: any_domain, bdf: bd_mean },
+	Builtin { name: "median", min_args: 1, max_args: VARIADIC, help: "median(a, b, ...): middle value",
+		integer_only: false, domain: any_domain, bdf: bd_median },
+	Builtin { name: "min", min_args: 1, max_args: VARIADIC, help: "min(a, b, ...): smallest value",
+		integer_only: false, domain: any_domain, bdf: bd_min },
+	Builtin { name: "max", min_args: 1, max_args: VARIADIC, help: "max(a, b, ...): largest value",
+		integer_only: false, domain: any_domain, bdf: bd_max },
+	Builtin { name: "var", min_args: 1, max_args: VARIADIC, help: "var(a, b, ...): population variance",
+		integer_only: false, domain: any_domain, bdf: bd_var },
+	Builtin { name: "vars", min_args: 2, max_args: VARIADIC, help: "vars(a, b, ...): sample variance",
+		integer_only: false, domain: any_domain, bdf: bd_vars },
+	Builtin { name: "stddev", min_args: 1, max_args: VARIADIC, help: "stddev(a, b, ...): population standard deviation",
+		integer_only: false, domain: any_domain, bdf: bd_stddev },
+	Builtin { name: "stddevs", min_args: 2, max_args: VARIADIC, help: "stddevs(a, b, ...): sample standard deviation",
+		integer_only: false, domain: any_domain, bdf: bd_stddevs },
+	Builtin { name: "geomean", min_args: 1, max_args: VARIADIC, help: "geomean(a, b, ...): geometric mean",
+		integer_only: false, domain: positive_values, bdf: bd_geomean },
+	Builtin { name: "harmonic", min_args: 1, max_args: VARIADIC, help: "harmonic(a, b, ...): harmonic mean",
+		integer_only: false, domain: positive_values, bdf: bd_harmonic },
+];
+
+// All the functions that expressions can call: the built-in ones, the
+// ones added with register(), and the ones defined by the user
+pub struct Registry {
+	funcs: Vec<Box<dyn Function>>, // sorted by name
+	pub user_funcs: UserFuncs
+}
+
+impl Registry {
+	// Registry with the built-in functions
+	pub fn new() -> Registry {
+		let mut ret = Registry { funcs: Vec::new(), user_funcs: UserFuncs::new() };
+		for builtin in BUILTINS.iter() {
+			ret.register(Box::new(builtin.clone()));
+		}
+		ret.register(Box::new(Factor));
+		for checksum in checksums::CHECKSUMS.iter() {
+			ret.register(Box::new(checksum.clone()));
+		}
+		for date_func in dates::DATE_FUNCS.iter() {
+			ret.register(Box::new(date_func.clone()));
+		}
+		ret
+	}
+
+	// Adds a function, or replaces the one with the same name
+	pub fn register(&mut self, func: Box<dyn Function>) {
+		match self.funcs.binary_search_by(|other| other.name().cmp(func.name())) {
+			Ok(index) => self.funcs[index] = func,
+			Err(index) => self.funcs.insert(index, func)
+		}
+	}
+
+	// Function that is not defined by the user
+	pub fn get(&self, name: &str) -> Option<&dyn Function> {
+		self.funcs.iter().find(|func| func.name() == name).map(|func| func.as_ref())
+	}
+
+	// Names of all the functions, sorted
+	pub fn names(&self) -> Vec<String> {
+		let mut ret: Vec<String> = self.funcs.iter().map(|func| func.name().to_string()).collect();
+		for user_func in self.user_funcs.funcs() {
+			if self.get(&user_func.name).is_none() {
+				ret.push(user_func.name.clone());
 			}
+		}
+		ret.sort();
+		ret
+	}
+
+	// One line description of a function, the definition for user functions
+	pub fn help(&self, name: &str) -> Option<String> {
+		if let Some(func) = self.get(name) {
+			return Some(func.help().to_string());
+		}
+		self.user_funcs.get(name).map(|user_func| user_func.text.clone())
+	}
 
+	// Calls a function that is not defined by the user, after checking
+	// its arguments
+	pub fn call(&self, name: &str, args: &[BigDec], settings: &Settings) -> Result<BigDec, Error> {
+		let func = match self.get(name) {
+			Some(func) => func,
+			None => return Err(Error::UnknownFunc)
+		};
+		let (min, max) = func.arity();
+		if args.len() < min || args.len() > max {
+			return Err(Error::WrongArgCount { name: name.to_string(), min, max, count: args.len() });
 		}
+		if func.integer_only() && args.iter().any(|arg| !arg.is_integer()) {
+			return Err(Error::NotInteger(name.to_string()));
+		}
+		if let Err(msg) = func.check_domain(args) {
+			return Err(Error::OutOfDomain(format!("{}: {}", name, msg)));
+		}
+		match func.call(args, settings) {
+			Ok(val) => Ok(val),
+			Err(err) => Err(Error::CallFailed(err))
+		}
+	}
+
+	// Calls a function that is not defined by the user with quantities,
+	// None if it only takes numbers
+	pub fn call_quantities(&self, name: &str, args: &[Quantity]) -> Option<Result<Quantity, Error>> {
+		let func = match self.get(name) {
+			Some(func) => func,
+			None => return None
+		};
+		let (min, max) = func.arity();
+		if args.len() < min || args.len() > max {
+			return Some(Err(Error::WrongArgCount { name: name.to_string(), min, max, count: args.len() }));
+		}
+		func.call_quantities(args)
 	}
-	Err(Error::UnknownFunc)
+
+	// Calls a function that is not defined by the user with the text of
+	// a string literal
+	pub fn call_bytes(&self, name: &str, bytes: &[u8]) -> Result<BigDec, Error> {
+		let func = match self.get(name) {
+			Some(func) => func,
+			None => return Err(Error::UnknownFunc)
+		};
+		match func.call_bytes(bytes) {
+			Some(Ok(val)) => Ok(val),
+			Some(Err(err)) => Err(Error::CallFailed(err)),
+			None => Err(Error::OutOfDomain(format!("{} does not accept strings", name)))
+		}
+	}
+}
+
+// Values that expressions can use by name, such as 2pi
+const CONSTANTS: [(&str, &str); 3] = [
+	("e", "2.71828182845904523536"),
+	("pi", "3.14159265358979323846"),
+	("tau", "6.28318530717958647693"),
+];
+
+pub fn constant(name: &str) -> Option<BigDec> {
+	CONSTANTS.iter()
+		.find(|&&(const_name, _)| const_name == name)
+		.map(|&(_, text)| BigDec::parse_str(text).unwrap())
 }
 
-fn bd_zero(_: BigDec) -> Result<BigDec, big_dec::Error> {
+fn deg_to_rad(arg: BigDec) -> Result<BigDec, big_dec::Error> {
+	let pi = constant("pi").unwrap();
+	let arg_pi = try!(BigDec::mul(arg, pi));
+	BigDec::div(arg_pi, BigDec::from_i32(180))
+}
+
+fn bd_zero(_: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
 	Ok(BigDec::zero())
 }
 
-fn bd_same(arg: BigDec) -> Result<BigDec, big_dec::Error> {
-	Ok(arg)
+fn bd_same(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	Ok(args[0])
+}
+
+fn bd_if(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	Ok(if args[0].is_zero() { args[2] } else { args[1] })
 }
 
-fn bd_sqrt(arg: BigDec) -> Result<BigDec, big_dec::Error> {
+fn bd_sqrt(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	sqrt(args[0])
+}
 
+fn sqrt(arg: BigDec) -> Result<BigDec, big_dec::Error> {
+	// The iterations would divide by r once it reaches 0
+	if arg.is_zero() {
+		return Ok(arg);
+	}
 	let mut r = BigDec::from_i32(1);
 	let two = BigDec::from_i32(2);
 
@@ -72,12 +499,22 @@ fn bd_sqrt(arg: BigDec) -> Result<BigDec, big_dec::Error> {
 #[test]
 fn test_sqrt() {
 	let n = BigDec::from_i32(25);
-	let res = bd_sqrt(n);
+	let res = sqrt(n);
 	assert!(res.is_ok());
 	assert_eq!(BigDec::from_i32(5), res.unwrap());
 }
 
-fn bd_cos(arg: BigDec) -> Result<BigDec, big_dec::Error> {
+fn bd_cos(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	// Trigonometric functions work with radians
+	if settings.angle_unit == AngleUnit::Degree {
+		cos(try!(deg_to_rad(args[0])))
+	}
+	else {
+		cos(args[0])
+	}
+}
+
+fn cos(arg: BigDec) -> Result<BigDec, big_dec::Error> {
 	let one = BigDec::from_i32(1);
 	let arg_square = try!(BigDec::mul(arg, arg));
 	let mut comp_result = one;
@@ -112,3 +549,975 @@ fn bd_cos(arg: BigDec) -> Result<BigDec, big_dec::Error> {
 	}
 	Ok(comp_result)
 }
+
+#[test]
+fn test_cos_degree() {
+	let mut settings = Settings::new();
+	settings.angle_unit = AngleUnit::Degree;
+	let res = Registry::new().call("cos", &[BigDec::from_i32(60)], &settings).ok().unwrap();
+	let rounded = BigDec::round(res, 10, big_dec::Rounding::HalfUp).unwrap();
+	assert_eq!("0.5", rounded.to_string());
+}
+
+// Integer functions. BigDec integers have 20 digits at most, so they
+// fit in i128 and their products modulo m are computed with u128.
+
+fn gcd_u(mut a: u128, mut b: u128) -> u128 {
+	while b != 0 {
+		let r = a % b;
+		a = b;
+		b = r;
+	}
+	a
+}
+
+// a*b modulo m, a and b being less than m
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+	if let Some(product) = a.checked_mul(b) {
+		return product % m;
+	}
+	// Double and add, m is below 2^67 so the sums do not overflow
+	let (mut a, mut b, mut ret) = (a, b, 0);
+	while b != 0 {
+		if b & 1 == 1 {
+			ret = (ret + a) % m;
+		}
+		a = (a + a) % m;
+		b >>= 1;
+	}
+	ret
+}
+
+fn pow_mod(base: u128, mut exp: u128, m: u128) -> u128 {
+	let mut base = base % m;
+	let mut ret = 1 % m;
+	while exp != 0 {
+		if exp & 1 == 1 {
+			ret = mul_mod(ret, base, m);
+		}
+		base = mul_mod(base, base, m);
+		exp >>= 1;
+	}
+	ret
+}
+
+// Miller-Rabin test. With these bases it is exact below 3.3e24, far
+// above the largest BigDec.
+fn is_prime_u(n: u128) -> bool {
+	const BASES: [u128; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+	if n < 2 {
+		return false;
+	}
+	for &p in BASES.iter() {
+		if n % p == 0 {
+			return n == p;
+		}
+	}
+	let mut d = n - 1;
+	let mut s = 0;
+	while d % 2 == 0 {
+		d /= 2;
+		s += 1;
+	}
+	'bases: for &a in BASES.iter() {
+		let mut x = pow_mod(a, d, n);
+		if x == 1 || x == n - 1 {
+			continue;
+		}
+		for _ in 1..s {
+			x = mul_mod(x, x, n);
+			if x == n - 1 {
+				continue 'bases;
+			}
+		}
+		return false;
+	}
+	true
+}
+
+// Steps of Pollard's rho allowed for one factorization, so that the
+// state label stays quick to update
+const RHO_STEPS: u32 = 20000;
+
+// A divisor of n other than 1 and n, n being composite without small
+// factors (Pollard's rho). None when steps run out first.
+fn find_divisor(n: u128, steps: &mut u32) -> Option<u128> {
+	let step = |x: u128, c: u128| (mul_mod(x, x, n) + c) % n;
+	let mut c = 1;
+	loop {
+		let (mut x, mut y, mut d) = (2, 2, 1);
+		while d == 1 {
+			if *steps == 0 {
+				return None;
+			}
+			*steps -= 1;
+			x = step(x, c);
+			y = step(step(y, c), c);
+			d = gcd_u(if x > y { x - y } else { y - x }, n);
+		}
+		if d != n {
+			return Some(d);
+		}
+		c += 1;
+	}
+}
+
+// r such that r^k is n, if any
+fn exact_root(n: u128, k: u32) -> Option<u128> {
+	let guess = (n as f64).powf(1.0 / k as f64).round() as u128;
+	(guess.saturating_sub(1)..guess + 2).find(|&r| r.checked_pow(k) == Some(n))
+}
+
+// Root and exponent of n if it is a power such as p^2
+fn perfect_power(n: u128) -> Option<(u128, u32)> {
+	(2..128 - n.leading_zeros()).filter_map(|k| exact_root(n, k).map(|root| (root, k))).next()
+}
+
+// Prime factors of n and their exponents, smallest first, then the
+// composite factors that Pollard's rho could not split in time
+fn prime_factors(mut n: u128) -> (Vec<(u128, u32)>, Vec<u128>) {
+	let mut primes = Vec::new();
+	// Small factors first, Pollard's rho is bad at them
+	let mut p = 2;
+	while p < 1000 && p * p <= n {
+		while n % p == 0 {
+			primes.push(p);
+			n /= p;
+		}
+		p += if p == 2 { 1 } else { 2 };
+	}
+	let mut composites = vec![n];
+	let mut unsplit = Vec::new();
+	let mut steps = RHO_STEPS;
+	while let Some(m) = composites.pop() {
+		if m == 1 {
+			continue;
+		}
+		if is_prime_u(m) {
+			primes.push(m);
+			continue;
+		}
+		// Pollard's rho is slow on powers of a prime, like p^2
+		if let Some((root, k)) = perfect_power(m) {
+			composites.extend(std::iter::repeat(root).take(k as usize));
+			continue;
+		}
+		match find_divisor(m, &mut steps) {
+			Some(d) => {
+				composites.push(d);
+				composites.push(m / d);
+			},
+			None => unsplit.push(m)
+		}
+	}
+	primes.sort();
+	unsplit.sort();
+
+	let mut ret: Vec<(u128, u32)> = Vec::new();
+	for prime in primes {
+		match ret.last_mut() {
+			Some(&mut (last, ref mut exp)) if last == prime => *exp += 1,
+			_ => ret.push((prime, 1))
+		}
+	}
+	(ret, unsplit)
+}
+
+fn bd_gcd(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let ret = args.iter().fold(0, |acc, arg| gcd_u(acc, arg.to_i128().abs() as u128));
+	BigDec::from_i128(ret as i128)
+}
+
+fn bd_lcm(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let mut ret: u128 = 1;
+	for arg in args {
+		let a = arg.to_i128().abs() as u128;
+		if a == 0 {
+			return Ok(BigDec::zero());
+		}
+		ret = match (ret / gcd_u(ret, a)).checked_mul(a) {
+			Some(val) if val <= std::i128::MAX as u128 => val,
+			_ => return Err(big_dec::Error::OpOverflow)
+		};
+	}
+	BigDec::from_i128(ret as i128)
+}
+
+fn bd_modpow(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let m = args[2].to_i128();
+	let base = args[0].to_i128().rem_euclid(m) as u128;
+	let ret = pow_mod(base, args[1].to_i128() as u128, m as u128);
+	BigDec::from_i128(ret as i128)
+}
+
+fn bd_modinv(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	// Extended Euclid, the domain check made sure that gcd(a, m) is 1
+	let m = args[1].to_i128();
+	let (mut r0, mut r1) = (args[0].to_i128().rem_euclid(m), m);
+	let (mut x0, mut x1): (i128, i128) = (1, 0);
+	while r1 != 0 {
+		let q = r0 / r1;
+		let r2 = r0 - q * r1;
+		r0 = r1;
+		r1 = r2;
+		let x2 = x0 - q * x1;
+		x0 = x1;
+		x1 = x2;
+	}
+	BigDec::from_i128(x0.rem_euclid(m))
+}
+
+fn bd_isprime(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let n = args[0].to_i128();
+	Ok(BigDec::from_i32(if n > 0 && is_prime_u(n as u128) { 1 } else { 0 }))
+}
+
+// factor(n) gives n back, the prime factors are shown in the state
+// label like 2^3 * 3 * 5
+struct Factor;
+
+impl Function for Factor {
+	fn name(&self) -> &str { "factor" }
+	fn arity(&self) -> (usize, usize) { (1, 1) }
+	fn help(&self) -> &str { "factor(n): n, showing its prime factors" }
+	fn integer_only(&self) -> bool { true }
+	fn check_domain(&self, args: &[BigDec]) -> Result<(), String> {
+		positive(args)
+	}
+	fn call(&self, args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+		Ok(args[0])
+	}
+	fn describe(&self, result: BigDec) -> Option<String> {
+		let n = result.to_i128();
+		if n < 1 {
+			return None;
+		}
+		if n == 1 {
+			return Some("1".to_string());
+		}
+		let (primes, unsplit) = prime_factors(n as u128);
+		if primes.is_empty() && !unsplit.is_empty() {
+			return Some("no small factors".to_string());
+		}
+		let mut texts: Vec<String> = primes.iter().map(|&(prime, exp)| {
+			if exp == 1 { prime.to_string() } else { format!("{}^{}", prime, exp) }
+		}).collect();
+		texts.extend(unsplit.iter().map(|m| m.to_string()));
+		let text = texts.join(" * ");
+		Some(if unsplit.is_empty() { text } else { format!("{} (no small factors left)", text) })
+	}
+}
+
+// Result of a call of a built-in function with the default settings,
+// rounded to 15 decimals, or the error message. The arguments are
+// written like in expressions, with an optional minus sign.
+#[cfg(test)]
+fn call(name: &str, args: &[&str]) -> String {
+	let parse = |arg: &str| if arg.starts_with('-') {
+		BigDec::parse_str(&arg[1..]).unwrap().negate()
+	}
+	else {
+		BigDec::parse_str(arg).unwrap()
+	};
+	let args: Vec<BigDec> = args.iter().map(|arg| parse(arg)).collect();
+	match Registry::new().call(name, &args, &Settings::new()) {
+		Ok(val) => BigDec::round(val, 15, big_dec::Rounding::HalfUp).unwrap().to_string(),
+		Err(err) => err.to_string()
+	}
+}
+
+#[test]
+fn test_number_theory() {
+	assert_eq!("6", call("gcd", &["12", "18"]));
+	assert_eq!("4", call("gcd", &["-8", "12", "20"]));
+	assert_eq!("36", call("lcm", &["12", "18"]));
+	assert_eq!("0", call("lcm", &["0", "5"]));
+	assert_eq!("overflow", call("lcm", &["99999999999999999989", "99999999999999999971"]));
+	assert_eq!("gcd only works with integers", call("gcd", &["1.5", "3"]));
+
+	assert_eq!("445", call("modpow", &["4", "13", "497"]));
+	assert_eq!("3", call("modpow", &["-2", "3", "11"]));
+	assert_eq!("1", call("modpow", &["99999999999999999988", "99999999999999999988", "99999999999999999989"]));
+	assert_eq!("modpow: the modulus must be positive", call("modpow", &["2", "3", "0"]));
+	assert_eq!("modpow: the exponent must not be negative", call("modpow", &["2", "-3", "5"]));
+	assert_eq!("4", call("modinv", &["3", "11"]));
+	assert_eq!("7", call("modinv", &["-3", "11"]));
+	assert_eq!("modinv: 6 has no inverse modulo 9", call("modinv", &["6", "9"]));
+
+	assert_eq!("1", call("isprime", &["2"]));
+	assert_eq!("0", call("isprime", &["1"]));
+	assert_eq!("0", call("isprime", &["561"]));
+	assert_eq!("1", call("isprime", &["18446744073709551557"]));
+	assert_eq!("1", call("isprime", &["99999999999999999989"]));
+	assert_eq!("0", call("isprime", &["99999999999999999999"]));
+
+	let registry = Registry::new();
+	let describe = |n: &str| registry.get("factor").unwrap().describe(BigDec::parse_str(n).unwrap()).unwrap();
+	assert_eq!("360", call("factor", &["360"]));
+	assert_eq!("2^3 * 3^2 * 5", describe("360"));
+	assert_eq!("1", describe("1"));
+	assert_eq!("71 * 839 * 1471 * 6857", describe("600851475143"));
+	assert_eq!("3^2 * 11 * 41 * 101 * 271 * 3541 * 9091 * 27961", describe("99999999999999999999"));
+	assert_eq!("9999999967^2", describe("99999999340000001089"));
+	assert_eq!("1009^6", describe("1055229678769825441"));
+	assert_eq!("no small factors", describe("99999999100000001881"));
+	assert_eq!("3 * 33333332861104027657 (no small factors left)", describe("99999998583312082971"));
+	assert_eq!("factor: the argument must be positive", call("factor", &["0"]));
+}
+
+// Combinatorics. Products are computed so that no intermediate value
+// is bigger than the result, instead of dividing factorials.
+
+// n choose k, exact. Each step gives the integer C(n-k+i, i).
+fn choose_u(n: u128, k: u128) -> Result<u128, big_dec::Error> {
+	if k > n {
+		return Ok(0);
+	}
+	let k = std::cmp::min(k, n - k);
+	let mut ret: u128 = 1;
+	for i in 1..k + 1 {
+		// ret*m/i is an integer and i/g divides m
+		let m = n - k + i;
+		let g = gcd_u(ret, i);
+		ret = match (ret / g).checked_mul(m / (i / g)) {
+			Some(val) => val,
+			None => return Err(big_dec::Error::OpOverflow)
+		};
+	}
+	Ok(ret)
+}
+
+fn from_u128(val: u128) -> Result<BigDec, big_dec::Error> {
+	if val > std::i128::MAX as u128 {
+		return Err(big_dec::Error::OpOverflow);
+	}
+	BigDec::from_i128(val as i128)
+}
+
+fn bd_ncr(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_u128(try!(choose_u(args[0].to_i128() as u128, args[1].to_i128() as u128)))
+}
+
+fn bd_npr(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let (n, k) = (args[0].to_i128() as u128, args[1].to_i128() as u128);
+	if k > n {
+		return Ok(BigDec::zero());
+	}
+	let mut ret: u128 = 1;
+	for i in 0..k {
+		ret = match ret.checked_mul(n - i) {
+			Some(val) if val <= std::i128::MAX as u128 => val,
+			_ => return Err(big_dec::Error::OpOverflow)
+		};
+	}
+	from_u128(ret)
+}
+
+// x (x-1) ... (x-k+1) / k!, exact for integers
+fn bd_binomial(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let (x, k) = (args[0], args[1].to_i128());
+	if x.is_integer() && !x.is_negative() {
+		return from_u128(try!(choose_u(x.to_i128() as u128, k as u128)));
+	}
+	if x.is_integer() {
+		// C(-n, k) = (-1)^k C(n+k-1, k)
+		let n = -x.to_i128();
+		let val = try!(from_u128(try!(choose_u((n + k - 1) as u128, k as u128))));
+		return Ok(if k % 2 == 1 { val.negate() } else { val });
+	}
+	let mut ret = BigDec::from_i32(1);
+	for i in 0..k {
+		let i = try!(BigDec::from_i128(i));
+		ret = try!(BigDec::mul(ret, try!(BigDec::sub(x, i))));
+		ret = try!(BigDec::div(ret, try!(BigDec::add(i, BigDec::from_i32(1)))));
+		// The next factors keep it 0
+		if ret.is_zero() {
+			break;
+		}
+	}
+	Ok(ret)
+}
+
+fn bd_fib(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let n = args[0].to_i128();
+	let (mut a, mut b): (i128, i128) = (0, 1);
+	for _ in 0..n {
+		let next = a + b;
+		a = b;
+		b = next;
+		// Stops long before i128 overflows
+		if a >= 100_000_000_000_000_000_000 {
+			return Err(big_dec::Error::OpOverflow);
+		}
+	}
+	BigDec::from_i128(a)
+}
+
+// x!, through the gamma function when x is not an integer
+pub fn factorial(x: BigDec) -> Result<BigDec, Error> {
+	let result = if x.is_integer() {
+		BigDec::fact(x)
+	}
+	else {
+		BigDec::add(x, BigDec::from_i32(1)).and_then(gamma)
+	};
+	result.map_err(Error::CallFailed)
+}
+
+// n!! = n (n-2) (n-4) ..., down to 1 or 2
+pub fn double_factorial(n: BigDec) -> Result<BigDec, Error> {
+	if !n.is_integer() || n.to_i128() < -1 {
+		return Err(Error::OutOfDomain("!!: the argument must be an integer of at least -1".to_string()));
+	}
+	let mut ret: i128 = 1;
+	let mut i = n.to_i128();
+	while i > 1 {
+		ret = match ret.checked_mul(i) {
+			Some(val) => val,
+			None => return Err(Error::CallFailed(big_dec::Error::OpOverflow))
+		};
+		i -= 2;
+	}
+	BigDec::from_i128(ret).map_err(Error::CallFailed)
+}
+
+fn bd_gamma(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	gamma(args[0])
+}
+
+// Gamma function, x not being 0 or a negative integer
+fn gamma(x: BigDec) -> Result<BigDec, big_dec::Error> {
+	let one = BigDec::from_i32(1);
+	if x.is_integer() {
+		return BigDec::fact(try!(BigDec::sub(x, one)));
+	}
+	// x = n + f, n being the integer below x
+	let mut n = x.to_i128();
+	if x.is_negative() {
+		n -= 1;
+	}
+	let f = try!(BigDec::sub(x, try!(BigDec::from_i128(n))));
+	if n == -1 {
+		// Gamma(x) = 1/x - 0.5772... there, the rounding of Gamma(1 + f)
+		// would hide that 1/x overflows
+		try!(BigDec::div(one, x));
+	}
+
+	// Gamma(f + n) from Gamma(1 + f) with Gamma(y + 1) = y Gamma(y)
+	let mut ret = try!(gamma_1p(f));
+	let mut i = 1;
+	while i < n {
+		ret = try!(BigDec::mul(ret, try!(BigDec::add(f, try!(BigDec::from_i128(i))))));
+		i += 1;
+	}
+	let mut i = n;
+	while i <= 0 && !ret.is_zero() {
+		ret = try!(BigDec::div(ret, try!(BigDec::add(f, try!(BigDec::from_i128(i))))));
+		i += 1;
+	}
+	Ok(ret)
+}
+
+// Gamma(1 + f) for f in [0, 1). The Stirling series is precise enough
+// far from 0, so ln Gamma(1 + f) comes from ln Gamma(1 + f + SHIFT).
+fn gamma_1p(f: BigDec) -> Result<BigDec, big_dec::Error> {
+	const SHIFT: i32 = 20;
+	let mut ln_gamma = try!(ln_gamma_stirling(try!(BigDec::add(f, BigDec::from_i32(SHIFT + 1)))));
+	for i in 1..SHIFT + 1 {
+		ln_gamma = try!(BigDec::sub(ln_gamma, try!(ln(try!(BigDec::add(f, BigDec::from_i32(i)))))));
+	}
+	exp(ln_gamma)
+}
+
+// ln Gamma(z) for z of 20 or more, the error is below 1e-25
+fn ln_gamma_stirling(z: BigDec) -> Result<BigDec, big_dec::Error> {
+	// B(2k) / (2k (2k-1))
+	const COEFS: [(i32, i32); 10] = [(1, 12), (-1, 360), (1, 1260), (-1, 1680), (1, 1188),
+		(-691, 360360), (1, 156), (-3617, 122400), (43867, 244188), (-174611, 125400)];
+	let half = BigDec::parse_str("0.5").unwrap();
+	let half_ln_2pi = BigDec::parse_str("0.91893853320467274178").unwrap();
+
+	// (z - 1/2) ln z - z + ln(2 pi)/2
+	let mut ret = try!(BigDec::mul(try!(BigDec::sub(z, half)), try!(ln(z))));
+	ret = try!(BigDec::add(try!(BigDec::sub(ret, z)), half_ln_2pi));
+
+	// Powers of 1/z since those of z overflow
+	let inv_z = try!(BigDec::div(BigDec::from_i32(1), z));
+	let inv_z_square = try!(BigDec::mul(inv_z, inv_z));
+	let mut inv_z_pow = inv_z; // 1/z^(2k-1)
+	for &(num, den) in COEFS.iter() {
+		let num_z = try!(BigDec::mul(BigDec::from_i32(num), inv_z_pow));
+		ret = try!(BigDec::add(ret, try!(BigDec::div(num_z, BigDec::from_i32(den)))));
+		inv_z_pow = try!(BigDec::mul(inv_z_pow, inv_z_square));
+	}
+	Ok(ret)
+}
+
+// Natural logarithm, x being positive
+fn ln(x: BigDec) -> Result<BigDec, big_dec::Error> {
+	let one = BigDec::from_i32(1);
+	let two = BigDec::from_i32(2);
+	let ln_2 = BigDec::parse_str("0.69314718055994530942").unwrap();
+
+	// x = m 2^k, m in [1, 2)
+	let mut m = x;
+	let mut k = 0;
+	while BigDec::compare_signed(m, two) >= 0 {
+		m = try!(BigDec::div(m, two));
+		k += 1;
+	}
+	while BigDec::compare_signed(m, one) < 0 {
+		m = try!(BigDec::mul(m, two));
+		k -= 1;
+	}
+
+	// ln m = 2 atanh(t) = 2 (t + t^3/3 + t^5/5 ...), t = (m-1)/(m+1) < 1/3
+	let t = try!(BigDec::div(try!(BigDec::sub(m, one)), try!(BigDec::add(m, one))));
+	let t_square = try!(BigDec::mul(t, t));
+	let mut t_pow = t;
+	let mut sum = BigDec::zero();
+	let mut n = 1;
+	loop {
+		let step = try!(BigDec::div(t_pow, BigDec::from_i32(n)));
+		if step.is_zero() {
+			break;
+		}
+		sum = try!(BigDec::add(sum, step));
+		t_pow = try!(BigDec::mul(t_pow, t_square));
+		n += 2;
+	}
+	let ln_m = try!(BigDec::add(sum, sum));
+	BigDec::add(ln_m, try!(BigDec::mul(BigDec::from_i32(k), ln_2)))
+}
+
+// e^x for small x
+fn exp(x: BigDec) -> Result<BigDec, big_dec::Error> {
+	let mut sum = BigDec::from_i32(1);
+	let mut step = BigDec::from_i32(1);
+	let mut n = 1;
+	loop {
+		step = try!(BigDec::div(try!(BigDec::mul(step, x)), BigDec::from_i32(n)));
+		if step.is_zero() {
+			break;
+		}
+		sum = try!(BigDec::add(sum, step));
+		n += 1;
+	}
+	Ok(sum)
+}
+
+#[test]
+fn test_combinatorics() {
+	assert_eq!("10", call("ncr", &["5", "2"]));
+	assert_eq!("0", call("ncr", &["2", "5"]));
+	assert_eq!("1", call("ncr", &["7", "0"]));
+	assert_eq!("overflow", call("ncr", &["100", "50"]));
+	assert_eq!("22451004309013280", call("ncr", &["200", "10"]));
+	assert_eq!("20", call("npr", &["5", "2"]));
+	assert_eq!("overflow", call("npr", &["100", "20"]));
+	assert_eq!("ncr: the argument must not be negative", call("ncr", &["-5", "2"]));
+
+	assert_eq!("10", call("binomial", &["5", "3"]));
+	assert_eq!("-10", call("binomial", &["-3", "3"]));
+	assert_eq!("0.0625", call("binomial", &["0.5", "3"]));
+	assert_eq!("binomial: k must be a non-negative integer", call("binomial", &["5", "1.5"]));
+	assert_eq!("binomial: k must be at most 1000 when x is not an integer", call("binomial", &["0.5", "100000"]));
+	assert_eq!("0", call("binomial", &["0.00000000000000000001", "1000"]));
+	assert_eq!("1", call("binomial", &["10000000000000000000", "0"]));
+
+	assert_eq!("0", call("fib", &["0"]));
+	assert_eq!("55", call("fib", &["10"]));
+	assert_eq!("83621143489848422977", call("fib", &["97"]));
+	assert_eq!("overflow", call("fib", &["98"]));
+	assert_eq!("overflow", call("fib", &["99999999999999999999"]));
+
+	assert_eq!("120", call("gamma", &["6"]));
+	assert_eq!("1.772453850905516", call("gamma", &["0.5"]));
+	assert_eq!("1.329340388179137", call("gamma", &["2.5"]));
+	assert_eq!("-3.544907701811032", call("gamma", &["-0.5"]));
+	assert_eq!("gamma: not defined for 0 and negative integers", call("gamma", &["-2"]));
+	assert_eq!("0", call("gamma", &["-1000000000000000000.5"]));
+	assert_eq!("overflow", call("gamma", &["-0.00000000000000000001"]));
+	assert_eq!("overflow", call("gamma", &["0.00000000000000000001"]));
+	assert_eq!("overflow", call("gamma", &["1000000000000000000.5"]));
+	assert!(factorial(BigDec::parse_str("0.99999999999999999999").unwrap().negate()).is_err());
+	assert_eq!("3.323350970447843", BigDec::round(factorial(BigDec::parse_str("2.5").unwrap()).ok().unwrap(),
+		15, big_dec::Rounding::HalfUp).unwrap().to_string());
+	assert_eq!("3840", double_factorial(BigDec::from_i32(10)).ok().unwrap().to_string());
+	assert_eq!("1", double_factorial(BigDec::from_i32(-1)).ok().unwrap().to_string());
+	assert!(double_factorial(BigDec::from_i32(-3)).is_err());
+}
+
+// Bits of a word, negative values being in two's complement
+fn to_word(arg: BigDec) -> u64 {
+	arg.to_i128() as u64
+}
+
+// The bits of val above width cleared
+fn low_bits(val: u64, width: u32) -> u64 {
+	if width >= 64 { val } else { val & ((1 << width) - 1) }
+}
+
+// The width argument at index, or the word size of the settings
+fn width_arg(args: &[BigDec], index: usize, settings: &Settings) -> u32 {
+	args.get(index).map_or(settings.word_size, |width| width.to_i128() as u32)
+}
+
+fn from_word(val: u64) -> Result<BigDec, big_dec::Error> {
+	BigDec::from_i128(val as i128)
+}
+
+fn bd_bswap16(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_word((to_word(args[0]) as u16).swap_bytes() as u64)
+}
+
+fn bd_bswap32(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_word((to_word(args[0]) as u32).swap_bytes() as u64)
+}
+
+fn bd_bswap64(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_word(to_word(args[0]).swap_bytes())
+}
+
+fn bd_popcount(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	let width = width_arg(args, 1, settings);
+	Ok(BigDec::from_i32(low_bits(to_word(args[0]), width).count_ones() as i32))
+}
+
+fn bd_clz(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	let width = width_arg(args, 1, settings);
+	let zeros = low_bits(to_word(args[0]), width).leading_zeros() - (64 - width);
+	Ok(BigDec::from_i32(zeros as i32))
+}
+
+fn bd_ctz(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	let width = width_arg(args, 1, settings);
+	let zeros = low_bits(to_word(args[0]), width).trailing_zeros();
+	Ok(BigDec::from_i32(std::cmp::min(zeros, width) as i32))
+}
+
+fn bd_bitrev(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	let width = width_arg(args, 1, settings);
+	from_word(low_bits(to_word(args[0]), width).reverse_bits() >> (64 - width))
+}
+
+// x rotated left by n bits within width bits, n being negative to
+// rotate right
+fn rotate_left(args: &[BigDec], n: i128, settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	let width = width_arg(args, 2, settings);
+	let val = low_bits(to_word(args[0]), width);
+	let n = n.rem_euclid(width as i128) as u32;
+	if n == 0 {
+		return from_word(val);
+	}
+	from_word(low_bits(val << n | val >> (width - n), width))
+}
+
+fn bd_rotl(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	rotate_left(args, args[1].to_i128(), settings)
+}
+
+fn bd_rotr(args: &[BigDec], settings: &Settings) -> Result<BigDec, big_dec::Error> {
+	rotate_left(args, -args[1].to_i128(), settings)
+}
+
+fn bd_bit(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_word(1 << args[0].to_i128())
+}
+
+fn mask(hi: u32, lo: u32) -> u64 {
+	low_bits(std::u64::MAX, hi - lo + 1) << lo
+}
+
+fn bd_mask(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	from_word(mask(args[0].to_i128() as u32, args[1].to_i128() as u32))
+}
+
+fn bd_field(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let (hi, lo) = (args[1].to_i128() as u32, args[2].to_i128() as u32);
+	from_word((to_word(args[0]) & mask(hi, lo)) >> lo)
+}
+
+fn bd_sext(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let bits = args[1].to_i128() as u32;
+	let shift = 64 - bits;
+	BigDec::from_i128(((to_word(args[0]) << shift) as i64 >> shift) as i128)
+}
+
+#[test]
+fn test_bits() {
+	let mut settings = Settings::new();
+	let registry = Registry::new();
+	let call = |name: &str, args: &[i128], settings: &Settings| {
+		let args: Vec<BigDec> = args.iter().map(|&arg| BigDec::from_i128(arg).unwrap()).collect();
+		match registry.call(name, &args, settings) {
+			Ok(val) => val.to_string(),
+			Err(err) => err.to_string()
+		}
+	};
+	assert_eq!(0x3412.to_string(), call("bswap16", &[0x1234], &settings));
+	assert_eq!(0x78563412.to_string(), call("bswap32", &[0x12345678], &settings));
+	assert_eq!(0xefcdab8967452301u64.to_string(), call("bswap64", &[0x0123456789abcdef], &settings));
+	assert_eq!("0", call("bswap16", &[0x10000], &settings));
+	assert_eq!("bswap64: the argument must fit in 64 bits", call("bswap64", &[1 << 64], &settings));
+
+	assert_eq!("32", call("popcount", &[-1], &settings));
+	assert_eq!("64", call("popcount", &[-1, 64], &settings));
+	assert_eq!("31", call("clz", &[1], &settings));
+	assert_eq!("15", call("clz", &[1, 16], &settings));
+	assert_eq!("32", call("clz", &[0], &settings));
+	assert_eq!("4", call("ctz", &[0x30], &settings));
+	assert_eq!("8", call("ctz", &[0, 8], &settings));
+	assert_eq!("clz: the width must be from 1 to 64", call("clz", &[1, 65], &settings));
+	assert_eq!(0x80000000u32.to_string(), call("bitrev", &[1], &settings));
+	assert_eq!("6", call("bitrev", &[3, 3], &settings));
+	settings.word_size = 8;
+	assert_eq!(0x81.to_string(), call("bitrev", &[0x81], &settings));
+
+	assert_eq!(0x0f.to_string(), call("rotl", &[0xf0, 4], &settings));
+	assert_eq!(0x0f.to_string(), call("rotr", &[0xf0, 4], &settings));
+	assert_eq!(0x2.to_string(), call("rotl", &[0x1, 1, 4], &settings));
+	assert_eq!(0x8.to_string(), call("rotr", &[0x1, 1, 4], &settings));
+	assert_eq!(0x8000000000000000u64.to_string(), call("rotr", &[1, 1, 64], &settings));
+	assert_eq!(0x1.to_string(), call("rotl", &[0x1, -4, 4], &settings));
+
+	assert_eq!("1024", call("bit", &[10], &settings));
+	assert_eq!("bit: bit positions go from 0 to 63", call("bit", &[64], &settings));
+	assert_eq!(0xf0.to_string(), call("mask", &[7, 4], &settings));
+	assert_eq!(std::u64::MAX.to_string(), call("mask", &[63, 0], &settings));
+	assert_eq!("mask: hi must not be below lo", call("mask", &[3, 4], &settings));
+	assert_eq!(0xb.to_string(), call("field", &[0xabcd, 11, 8], &settings));
+	assert_eq!("-1", call("sext", &[0xff, 8], &settings));
+	assert_eq!("127", call("sext", &[0x7f, 8], &settings));
+	assert_eq!("-2048", call("sext", &[0x800, 12], &settings));
+	assert_eq!("sext: the width must be from 1 to 64", call("sext", &[1, 0], &settings));
+}
+
+#[cfg(test)]
+struct Twice;
+
+#[cfg(test)]
+impl Function for Twice {
+	fn name(&self) -> &str { "twice" }
+	fn arity(&self) -> (usize, usize) { (1, 1) }
+	fn help(&self) -> &str { "twice(n): 2n" }
+	fn integer_only(&self) -> bool { true }
+	fn call(&self, args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+		BigDec::add(args[0], args[0])
+	}
+}
+
+// The statistics are computed from exact sums, dividing last, so that
+// only the final divisions round
+
+fn sum(args: &[BigDec]) -> Result<BigDec, big_dec::Error> {
+	let mut ret = BigDec::zero();
+	for &arg in args {
+		ret = try!(BigDec::add(ret, arg));
+	}
+	Ok(ret)
+}
+
+fn count(args: &[BigDec]) -> BigDec {
+	BigDec::from_i128(args.len() as i128).unwrap()
+}
+
+fn bd_sum(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	sum(args)
+}
+
+fn bd_mean(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	BigDec::div(try!(sum(args)), count(args))
+}
+
+fn bd_median(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let mut sorted = args.to_vec();
+	sorted.sort_by(|&a, &b| BigDec::compare_signed(a, b).cmp(&0));
+	let middle = sorted.len() / 2;
+	if sorted.len() % 2 == 1 {
+		return Ok(sorted[middle]);
+	}
+	BigDec::div(try!(BigDec::add(sorted[middle - 1], sorted[middle])), BigDec::from_i32(2))
+}
+
+fn bd_min(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	Ok(args.iter().fold(args[0], |acc, &arg| if BigDec::compare_signed(arg, acc) < 0 { arg } else { acc }))
+}
+
+fn bd_max(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	Ok(args.iter().fold(args[0], |acc, &arg| if BigDec::compare_signed(arg, acc) > 0 { arg } else { acc }))
+}
+
+// sum((x - mean)^2) / d, d being n for the population and n - 1 for a
+// sample. Squaring the deviations rather than the values keeps large
+// values that are close to each other in range.
+fn variance(args: &[BigDec], sample: bool) -> Result<BigDec, big_dec::Error> {
+	let mean = try!(BigDec::div(try!(sum(args)), count(args)));
+	let mut squares = Vec::new();
+	for &arg in args {
+		let deviation = try!(BigDec::sub(arg, mean));
+		squares.push(try!(BigDec::mul(deviation, deviation)));
+	}
+	let d = if sample { count(&args[1..]) } else { count(args) };
+	BigDec::div(try!(sum(&squares)), d)
+}
+
+fn bd_var(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	variance(args, false)
+}
+
+fn bd_vars(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	variance(args, true)
+}
+
+fn bd_stddev(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	sqrt(try!(variance(args, false)))
+}
+
+fn bd_stddevs(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	sqrt(try!(variance(args, true)))
+}
+
+// n-th root of the product of the values. It is estimated as e^(mean of
+// ln x), computed as e^f 2^k with f in [0, ln 2) since exp() wants small
+// arguments. The rounding errors of the logarithms grow with the result,
+// so from 1 on it is then refined on the exact product. Below 1, the
+// product loses more decimals than the estimate, which is kept.
+fn bd_geomean(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	if args.iter().all(|&arg| BigDec::compare_signed(arg, args[0]) == 0) {
+		return Ok(args[0]);
+	}
+	let one = BigDec::from_i32(1);
+	let mut product = one;
+	for &arg in args {
+		product = try!(BigDec::mul(product, arg));
+	}
+
+	let mut logs = Vec::new();
+	for &arg in args {
+		logs.push(try!(ln(arg)));
+	}
+	let mean_log = try!(BigDec::div(try!(sum(&logs)), count(args)));
+	let ln_2 = BigDec::parse_str("0.69314718055994530942").unwrap();
+	let mut k = try!(BigDec::div(mean_log, ln_2)).to_i128();
+	if mean_log.is_negative() {
+		k -= 1;
+	}
+	let f = try!(BigDec::sub(mean_log, try!(BigDec::mul(try!(BigDec::from_i128(k)), ln_2))));
+	let mut ret = try!(exp(f));
+	let two = BigDec::from_i32(2);
+	for _ in 0..k.abs() {
+		ret = try!(if k > 0 { BigDec::mul(ret, two) } else { BigDec::div(ret, two) });
+	}
+
+	if BigDec::compare_signed(product, one) < 0 {
+		return Ok(ret);
+	}
+
+	// Newton's method on r^n = product: r = ((n - 1) r + product / r^(n - 1)) / n
+	let n = count(args);
+	let n_minus_1 = count(&args[1..]);
+	for _ in 0..BigDec::max_len() {
+		let mut power = one;
+		for _ in 1..args.len() {
+			power = try!(BigDec::mul(power, ret));
+		}
+		let sum = try!(BigDec::add(try!(BigDec::mul(n_minus_1, ret)), try!(BigDec::div(product, power))));
+		let next = try!(BigDec::div(sum, n));
+		if BigDec::compare(next, ret) == 0 {
+			break;
+		}
+		ret = next;
+	}
+	Ok(ret)
+}
+
+fn bd_harmonic(args: &[BigDec], _: &Settings) -> Result<BigDec, big_dec::Error> {
+	let mut inverses = Vec::new();
+	for &arg in args {
+		inverses.push(try!(BigDec::div(BigDec::from_i32(1), arg)));
+	}
+	BigDec::div(count(args), try!(sum(&inverses)))
+}
+
+#[test]
+fn test_statistics() {
+	let settings = Settings::new();
+	let registry = Registry::new();
+	let data = ["2", "4", "4", "4", "5", "5", "7", "9"];
+	assert_eq!("40", call("sum", &data));
+	assert_eq!("5", call("mean", &data));
+	assert_eq!("5", call("avg", &data));
+	assert_eq!("4.5", call("median", &data));
+	assert_eq!("4", call("median", &["9", "-1", "4"]));
+	assert_eq!("-1", call("min", &["9", "-1", "4"]));
+	assert_eq!("9", call("max", &["9", "-1", "4"]));
+	assert_eq!("4", call("var", &data));
+	assert_eq!("2", call("stddev", &data));
+	assert_eq!("4.571428571428571", call("vars", &data));
+	assert_eq!("2.138089935299395", call("stddevs", &data));
+	assert_eq!("0", call("var", &["3"]));
+	assert_eq!("0.333333333333333", call("mean", &["0.1", "0.2", "0.7", "0.3", "0.1", "0.6"]));
+	// Without rounding
+	let exact = |name: &str, args: &[&str]| {
+		let args: Vec<BigDec> = args.iter().map(|arg| BigDec::parse_str(arg).unwrap()).collect();
+		match registry.call(name, &args, &settings) {
+			Ok(val) => val.to_string(),
+			Err(err) => err.to_string()
+		}
+	};
+	assert_eq!("0.00000000000000000002", exact("mean", &["0.00000000000000000001", "0.00000000000000000003"]));
+	assert_eq!("0.25", exact("var", &["10000000000", "10000000001"]));
+	assert_eq!("0.5", exact("vars", &["10000000000", "10000000001"]));
+	assert_eq!("0", exact("stddev", &["1", "1"]));
+	assert_eq!("0", exact("stddev", &["0", "0"]));
+	assert_eq!("0", exact("stddevs", &["5", "5", "5"]));
+	assert_eq!("0", exact("sqrt", &["0"]));
+
+	assert_eq!("4", exact("geomean", &["2", "8"]));
+	assert_eq!("12345", exact("geomean", &["12345"]));
+	assert_eq!("99999999999999999999", exact("geomean", &["99999999999999999999", "99999999999999999999"]));
+	assert_eq!("0.00000000000000000001", exact("geomean", &["0.00000000000000000001", "0.00000000000000000001"]));
+	assert_eq!("6", exact("geomean", &["4", "9", "6"]));
+	assert_eq!("2469135780", exact("geomean", &["1234567890", "4938271560"]));
+	assert_eq!("overflow", exact("geomean", &["99999999999999999999", "99999999999999999998"]));
+	assert_eq!("0.01", call("geomean", &["0.0001", "1"]));
+	assert_eq!("0.3", call("geomean", &["0.1", "0.9"]));
+	assert_eq!("1.714285714285714", call("harmonic", &["1", "2", "4"]));
+	assert_eq!("geomean: the values must be positive", call("geomean", &["2", "0"]));
+	assert_eq!("mean expects at least 1 argument, not 0", call("mean", &[]));
+	assert_eq!("stddevs expects at least 2 arguments, not 1", call("stddevs", &["3"]));
+}
+
+#[test]
+fn test_registry() {
+	let settings = Settings::new();
+	let mut registry = Registry::new();
+	registry.register(Box::new(Twice));
+	assert_eq!(vec!["adler32", "avg", "binomial", "bit", "bitrev", "bswap16", "bswap32", "bswap64", "clz", "cos",
+		"crc16", "crc16ccitt", "crc16modbus", "crc32", "crc8", "ctz", "factor", "fib", "field", "fnv1a", "fnv1a64",
+		"fromunix", "gamma", "gcd", "geomean", "harmonic", "if", "isprime", "lcm", "mask", "max", "mean", "median",
+		"min", "modinv", "modpow", "ncr", "npr", "popcount", "rotl", "rotr", "same", "sext", "sqrt", "stddev",
+		"stddevs", "sum", "twice", "unixtime", "var", "vars", "zero"], registry.names());
+	assert_eq!(Some("unixtime(date): seconds since 1970-01-01, in UTC".to_string()), registry.help("unixtime"));
+	assert_eq!(Some("sqrt(x): square root".to_string()), registry.help("sqrt"));
+	assert_eq!(None, registry.help("nope"));
+
+	let call = |name: &str, args: &[i32]| {
+		let args: Vec<BigDec> = args.iter().map(|&arg| BigDec::from_i32(arg)).collect();
+		match registry.call(name, &args, &settings) {
+			Ok(val) => val.to_string(),
+			Err(err) => err.to_string()
+		}
+	};
+	assert_eq!("14", call("twice", &[7]));
+	assert_eq!("twice expects 1 argument, not 2", call("twice", &[7, 8]));
+	assert_eq!("sqrt: the argument must not be negative", call("sqrt", &[-4]));
+	assert_eq!("unknown func", call("nope", &[1]));
+	assert_eq!("3421780262", registry.call_bytes("crc32", b"123456789").ok().unwrap().to_string());
+	assert_eq!("sqrt does not accept strings", registry.call_bytes("sqrt", b"4").err().unwrap().to_string());
+
+	let half = BigDec::parse_str("0.5").unwrap();
+	assert_eq!("twice only works with integers",
+		registry.call("twice", &[half], &settings).err().unwrap().to_string());
+}
@@ -0,0 +1,74 @@
+use gtk;
+use gtk::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use funcs::Registry;
+
+// Rebuilds the rows, one per function with its description. Functions
+// defined by the user have a button to delete them.
+fn refresh(list_box: &gtk::ListBox, registry: &Rc<RefCell<Registry>>, on_change: &Rc<dyn Fn()>) {
+	for child in list_box.get_children() {
+		list_box.remove(&child);
+	}
+
+	let names = registry.borrow().names();
+	for name in names {
+		let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+
+		let help = registry.borrow().help(&name).unwrap_or_default();
+		let label = gtk::Label::new(Some(help.as_str()));
+		gtk::WidgetExt::set_name(&label, "history");
+		label.set_xalign(0.0);
+		label.set_selectable(true);
+		label.set_hexpand(true);
+		row_box.pack_start(&label, true, true, 0);
+
+		let is_user_func = registry.borrow().get(&name).is_none();
+		if is_user_func {
+			let delete_button = gtk::Button::new_from_icon_name("edit-delete-symbolic",
+				gtk::IconSize::Button.into());
+			delete_button.set_relief(gtk::ReliefStyle::None);
+			delete_button.set_tooltip_text("Delete");
+			delete_button.connect_clicked({
+				let list_box = list_box.clone();
+				let registry = registry.clone();
+				let on_change = on_change.clone();
+				move |_| {
+					registry.borrow_mut().user_funcs.remove(&name);
+					on_change();
+					refresh(&list_box, &registry, &on_change);
+				}
+			});
+			row_box.pack_start(&delete_button, false, false, 0);
+		}
+
+		let row = gtk::ListBoxRow::new();
+		row.add(&row_box);
+		row.show_all();
+		list_box.add(&row);
+	}
+}
+
+// Opens the list of the functions. on_change is called after a user
+// function is deleted.
+pub fn show_function_list(parent: &gtk::ApplicationWindow, registry: Rc<RefCell<Registry>>,
+	on_change: Rc<dyn Fn()>) {
+
+	let window = gtk::Window::new(gtk::WindowType::Toplevel);
+	window.set_title("Functions");
+	window.set_transient_for(Some(parent));
+	window.set_destroy_with_parent(true);
+	window.set_modal(true);
+	window.set_default_size(300, 300);
+
+	let scrolled_window = gtk::ScrolledWindow::new(None, None);
+	scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+	window.add(&scrolled_window);
+
+	let list_box = gtk::ListBox::new();
+	list_box.set_selection_mode(gtk::SelectionMode::None);
+	scrolled_window.add(&list_box);
+
+	refresh(&list_box, &registry, &on_change);
+	window.show_all();
+}
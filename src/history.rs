@@ -0,0 +1,158 @@
+use eval;
+use settings::Settings;
+use funcs::Registry;
+
+const MAX_ENTRIES: usize = 200; // oldest entries are dropped past this count
+
+pub struct HistoryEntry {
+	pub expression: String,
+	pub result: String, // in the number style of the settings, error message if ok is false
+	pub ok: bool
+}
+
+impl HistoryEntry {
+	fn evaluate(expression: &str, settings: &Settings, registry: &Registry) -> HistoryEntry {
+		let (result, ok) = match eval::eval_debug(expression, settings, registry, false) {
+			Ok(nv) => (eval::format_result(nv, settings), true),
+			Err(err) => (err.to_string(), false)
+		};
+		HistoryEntry { expression: expression.into(), result, ok }
+	}
+}
+
+// Committed expressions with their results, oldest first
+pub struct History {
+	entries: Vec<HistoryEntry>
+}
+
+impl History {
+	pub fn new() -> History {
+		History { entries: Vec::new() }
+	}
+
+	pub fn entries(&self) -> &[HistoryEntry] {
+		&self.entries
+	}
+
+	// Evaluates the expression and stores it with its result.
+	// Returns false if the expression cannot be evaluated, in which
+	// case nothing is stored.
+	pub fn commit(&mut self, expression: &str, settings: &Settings, registry: &Registry) -> bool {
+		let expression = expression.trim();
+		if expression.is_empty() {
+			return false;
+		}
+		let entry = HistoryEntry::evaluate(expression, settings, registry);
+		if !entry.ok {
+			return false;
+		}
+		// Committing the same thing twice in a row is not useful
+		if let Some(last) = self.entries.last() {
+			if last.expression == entry.expression && last.result == entry.result {
+				return true;
+			}
+		}
+		if self.entries.len() == MAX_ENTRIES {
+			self.entries.remove(0);
+		}
+		self.entries.push(entry);
+		true
+	}
+
+	pub fn remove(&mut self, index: usize) {
+		if index < self.entries.len() {
+			self.entries.remove(index);
+		}
+	}
+
+	// One expression per line, oldest first. Results are not stored
+	// since they are computed again when reading.
+	pub fn to_text(&self) -> String {
+		let mut ret = String::new();
+		for entry in &self.entries {
+			ret.push_str(&entry.expression);
+			ret.push('\n');
+		}
+		ret
+	}
+
+	// Reads a history written by to_text(). Lines that cannot be
+	// evaluated are dropped.
+	pub fn from_text(text: &str, settings: &Settings, registry: &Registry) -> History {
+		let mut ret = History::new();
+		for line in text.lines() {
+			ret.commit(line, settings, registry);
+		}
+		ret
+	}
+
+	// Computes the results again, to be called when a setting that
+	// changes the results is modified, or a user function
+	pub fn reevaluate(&mut self, settings: &Settings, registry: &Registry) {
+		for entry in self.entries.iter_mut() {
+			*entry = HistoryEntry::evaluate(&entry.expression, settings, registry);
+		}
+	}
+}
+
+#[test]
+fn test_history_commit() {
+	let mut settings = Settings::new();
+	let registry = Registry::new();
+	let mut history = History::new();
+	assert!(history.commit("1+2", &settings, &registry));
+	assert!(!history.commit("1+", &settings, &registry));
+	assert!(!history.commit("  ", &settings, &registry));
+	assert!(history.commit("6!", &settings, &registry));
+	assert!(history.commit("6!", &settings, &registry));
+	assert_eq!(2, history.entries().len());
+	assert_eq!("1+2", history.entries()[0].expression);
+	assert_eq!("3", history.entries()[0].result);
+	assert_eq!("720", history.entries()[1].result);
+
+	history.remove(0);
+	history.remove(5);
+	assert_eq!(1, history.entries().len());
+	assert_eq!("6!", history.entries()[0].expression);
+
+	history.reevaluate(&settings, &registry);
+	assert_eq!("720", history.entries()[0].result);
+	assert!(history.entries()[0].ok);
+
+	settings.int_digits = 2;
+	history.reevaluate(&settings, &registry);
+	assert_eq!("overflow", history.entries()[0].result);
+	assert!(!history.entries()[0].ok);
+}
+
+#[test]
+fn test_history_styles() {
+	use format::NumberStyle;
+	let mut settings = Settings::new();
+	let registry = Registry::new();
+	for &style in &[NumberStyle::Plain, NumberStyle::English, NumberStyle::European, NumberStyle::French,
+		NumberStyle::Swiss] {
+		settings.number_style = style;
+		let mut history = History::new();
+		for expression in &["1234 / 1000", "1234567 / 2", "1234 m / 1000", "250ms"] {
+			assert!(history.commit(expression, &settings, &registry));
+		}
+		for (entry, expected) in history.entries().iter().zip(&["1.234", "617283.5", "1.234 m", "0.25s"]) {
+			// Inserting the result in the entry gives it back
+			let again = eval::eval_debug(&entry.result, &settings, &registry, false).ok().unwrap();
+			assert_eq!(expected.to_string(), again.to_string());
+		}
+	}
+	settings.number_style = NumberStyle::European;
+	let mut history = History::new();
+	history.commit("1234 / 1000", &settings, &registry);
+	assert_eq!("1,234", history.entries()[0].result);
+}
+
+#[test]
+fn test_history_text() {
+	let history = History::from_text("1+2\n\n2*(\n7%5\n", &Settings::new(), &Registry::new());
+	assert_eq!(2, history.entries().len());
+	assert_eq!("2", history.entries()[1].result);
+	assert_eq!("1+2\n7%5\n", history.to_text());
+}
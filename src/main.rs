@@ -1,5 +1,6 @@
 extern crate gtk;
 extern crate gio;
+extern crate pango;
 
 use gtk::prelude::*;
 use gio::prelude::*;
@@ -15,12 +16,30 @@ use gtk::{ApplicationWindow};
 
 //use glib::{self, Variant};
 use std::env::args;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
 
 mod text_canvas;
 mod eval;
 mod big_dec;
 mod token;
 mod funcs;
+mod error;
+mod history;
+mod settings;
+mod storage;
+mod preferences;
+mod bit_panel;
+mod format;
+mod user_funcs;
+mod units;
+mod dates;
+mod checksums;
+mod function_list;
+mod completion;
+
+use settings::Settings;
+use funcs::Registry;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -83,7 +102,8 @@ impl Header {
 		header_bar.pack_end(&hamburger_button);
 
 		let menu = gio::Menu::new();
-		//menu.append("Preferences", "win.preferences");
+		menu.append("Functions", "win.functions");
+		menu.append("Preferences", "win.preferences");
 	  	menu.append("About", "win.about");
 
 		let popover = gtk::Popover::new_from_model(&hamburger_button, &menu);
@@ -118,13 +138,287 @@ fn show_about(window: &ApplicationWindow) {
 	ad.destroy();
 }
 
+// List of the committed expressions, newest first
+struct HistoryPanel {
+	pub scrolled_window: gtk::ScrolledWindow,
+	list_box: gtk::ListBox,
+	entry: gtk::Entry,
+	settings: Rc<RefCell<Settings>>,
+	registry: Rc<RefCell<Registry>>,
+	history: RefCell<history::History>
+}
+
+impl HistoryPanel {
+	fn new(entry: &gtk::Entry, settings: Rc<RefCell<Settings>>, registry: Rc<RefCell<Registry>>)
+		-> Rc<HistoryPanel> {
+		let scrolled_window = gtk::ScrolledWindow::new(None, None);
+		scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+		scrolled_window.set_min_content_height(120);
+		scrolled_window.set_vexpand(true);
+
+		let list_box = gtk::ListBox::new();
+		list_box.set_selection_mode(gtk::SelectionMode::None);
+		let placeholder = gtk::Label::new(Some("Press Enter to keep a result here"));
+		gtk::WidgetExt::set_name(&placeholder, "state");
+		placeholder.show();
+		list_box.set_placeholder(Some(&placeholder));
+		scrolled_window.add(&list_box);
+
+		let history = storage::load_history(&settings.borrow(), &registry.borrow());
+		let panel = Rc::new(HistoryPanel {
+			scrolled_window,
+			list_box,
+			entry: entry.clone(),
+			settings,
+			registry,
+			history: RefCell::new(history)
+		});
+		HistoryPanel::refresh(&panel);
+
+		// Clicking an entry puts its expression back in the input box
+		panel.list_box.connect_row_activated({
+			let panel = panel.clone();
+			move |_, row| {
+				if let Some(index) = panel.index_from_row(row.get_index()) {
+					let expression = panel.history.borrow().entries()[index].expression.clone();
+					panel.entry.set_text(&expression);
+					panel.entry.grab_focus_without_selecting();
+					panel.entry.set_position(-1);
+				}
+			}
+		});
+		panel
+	}
+
+	// Rows are displayed in the reverse order of the history
+	fn index_from_row(&self, row_index: i32) -> Option<usize> {
+		let len = self.history.borrow().entries().len();
+		if row_index < 0 || row_index as usize >= len {
+			return None;
+		}
+		Some(len - 1 - row_index as usize)
+	}
+
+	fn commit(panel: &Rc<HistoryPanel>, expression: &str) -> bool {
+		let committed = panel.history.borrow_mut().commit(expression, &panel.settings.borrow(),
+			&panel.registry.borrow());
+		if committed {
+			HistoryPanel::refresh(panel);
+			storage::save_history(&panel.history.borrow());
+		}
+		committed
+	}
+
+	fn reevaluate(panel: &Rc<HistoryPanel>) {
+		panel.history.borrow_mut().reevaluate(&panel.settings.borrow(), &panel.registry.borrow());
+		HistoryPanel::refresh(panel);
+	}
+
+	fn insert_result(&self, index: usize) {
+		let result = self.history.borrow().entries()[index].result.clone();
+		let mut pos = self.entry.get_position();
+		self.entry.insert_text(&result, &mut pos);
+		self.entry.grab_focus_without_selecting();
+		self.entry.set_position(pos);
+	}
+
+	// Rebuilds all the rows from the history
+	fn refresh(panel: &Rc<HistoryPanel>) {
+		for child in panel.list_box.get_children() {
+			panel.list_box.remove(&child);
+		}
+
+		let history = panel.history.borrow();
+		for (index, he) in history.entries().iter().enumerate().rev() {
+			let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+
+			let text = format!("{} = {}", he.expression, he.result);
+			let label = gtk::Label::new(Some(text.as_str()));
+			gtk::WidgetExt::set_name(&label, if he.ok { "history" } else { "state" });
+			label.set_xalign(0.0);
+			label.set_ellipsize(pango::EllipsizeMode::End);
+			label.set_hexpand(true);
+			row_box.pack_start(&label, true, true, 0);
+
+			let insert_button = gtk::Button::new_from_icon_name("insert-text-symbolic",
+				gtk::IconSize::Button.into());
+			insert_button.set_relief(gtk::ReliefStyle::None);
+			insert_button.set_tooltip_text("Insert the result in the expression");
+			insert_button.set_sensitive(he.ok);
+			insert_button.connect_clicked({
+				let panel = panel.clone();
+				move |_| { panel.insert_result(index); }
+			});
+			row_box.pack_start(&insert_button, false, false, 0);
+
+			let delete_button = gtk::Button::new_from_icon_name("edit-delete-symbolic",
+				gtk::IconSize::Button.into());
+			delete_button.set_relief(gtk::ReliefStyle::None);
+			delete_button.set_tooltip_text("Delete");
+			delete_button.connect_clicked({
+				let panel = panel.clone();
+				move |_| {
+					panel.history.borrow_mut().remove(index);
+					HistoryPanel::refresh(&panel);
+					storage::save_history(&panel.history.borrow());
+				}
+			});
+			row_box.pack_start(&delete_button, false, false, 0);
+
+			let row = gtk::ListBoxRow::new();
+			row.add(&row_box);
+			row.show_all();
+			panel.list_box.add(&row);
+		}
+	}
+}
+
+// Evaluates the input box and displays the result
+struct ResultView {
+	entry: gtk::Entry,
+	label_state: gtk::Label,
+	label_result_dec: gtk::Label,
+	label_result_hex: gtk::Label,
+	label_result_char: gtk::Label,
+	label_result_bin: gtk::Label,
+	row_dec: gtk::Box,
+	row_hex: gtk::Box,
+	row_char: gtk::Box,
+	row_bin: gtk::Box,
+	copy_actions: Vec<SimpleAction>, // disabled when there is no result
+	bit_panel: Rc<bit_panel::BitPanel>,
+	settings: Rc<RefCell<Settings>>,
+	registry: Rc<RefCell<Registry>>,
+	error_span: Cell<Option<error::Span>>, // where the last error comes from, if any
+	debug_mode: bool
+}
+
+impl ResultView {
+	fn update(&self) {
+		let settings = self.settings.borrow();
+		self.row_dec.set_visible(settings.show_dec);
+		self.row_hex.set_visible(settings.show_hex);
+		self.row_char.set_visible(settings.show_char);
+		self.row_bin.set_visible(settings.show_bin);
+
+		if let Some(str) = self.entry.get_chars(0, -1) {
+			let de = eval::eval_input_debug_detailed(&str, &settings, &self.registry.borrow(),
+				self.debug_mode);
+			self.label_state.set_label(&de.state_str);
+			self.label_result_dec.set_label(&de.result_dec);
+			self.label_result_hex.set_label(&de.result_hex);
+			self.label_result_char.set_label(&de.result_char);
+			self.label_result_bin.set_label(&de.result_bin);
+			show_error_span(&self.entry, &str, de.error.as_ref());
+			for action in &self.copy_actions {
+				action.set_enabled(de.error.is_none());
+			}
+			self.error_span.set(de.error.map(|err| err.span()));
+			bit_panel::BitPanel::update(&self.bit_panel, de.value, settings.word_size, settings.show_bits);
+		}
+	}
+}
+
+// A result label followed by a button to copy it
+fn new_result_row(label: &gtk::Label, action_name: &str, tooltip: &str) -> gtk::Box {
+	let row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+	row.pack_start(label, true, true, 0);
+	let copy_button = gtk::Button::new_from_icon_name("edit-copy-symbolic",
+		gtk::IconSize::Button.into());
+	copy_button.set_relief(gtk::ReliefStyle::None);
+	copy_button.set_tooltip_text(tooltip);
+	copy_button.set_action_name(action_name);
+	row.pack_start(&copy_button, false, false, 0);
+	row
+}
+
+// Action that puts the text of a label in the clipboard
+fn new_copy_action(window: &ApplicationWindow, name: &str, label: &gtk::Label) -> SimpleAction {
+	let sa = SimpleAction::new(name, None);
+	window.add_action(&sa);
+	sa.connect_activate({
+		let label = label.clone();
+		move |_, _| {
+			let display = match label.get_display() {
+				Some(display) => display,
+				None => return
+			};
+			if let (Some(clipboard), Some(text)) = (gtk::Clipboard::get_default(&display), label.get_text()) {
+				clipboard.set_text(&text);
+			}
+		}
+	});
+	sa
+}
+
+// Replaces the default paste of the entry to clean up the pasted text
+fn paste_cleaned(entry: &gtk::Entry) {
+	let display = match entry.get_display() {
+		Some(display) => display,
+		None => return
+	};
+	let text = match gtk::Clipboard::get_default(&display).and_then(|clipboard| clipboard.wait_for_text()) {
+		Some(text) => text,
+		None => return
+	};
+	entry.delete_selection();
+	let mut pos = entry.get_position();
+	entry.insert_text(&token::clean_pasted(&text), &mut pos);
+	entry.set_position(pos);
+}
+
+fn load_css(css_provider: &gtk::CssProvider, settings: &Settings) -> bool {
+	let css = format!("#state {{ color: #800; }} \
+		#result {{ font-family: monospace; font-size: {}px; }} \
+		#history {{ font-family: monospace; }} \
+		#bit {{ font-family: monospace; padding: 0; }} \
+		#bit_index {{ font-size: 8px; color: #888; }} \
+		#help {{ color: #666; }}", settings.font_size);
+	if let Err(err) = css_provider.load_from_data(css.as_bytes()) {
+		println!("css_provider.load_from_data failed: {}", err);
+		return false;
+	}
+	true
+}
+
+// Underlines the part of the input an error comes from, and shows the
+// error message in the tooltip of the entry icon
+fn show_error_span(entry: &gtk::Entry, text: &str, err: Option<&error::Error>) {
+	let attrs = pango::AttrList::new();
+	match err {
+		Some(err) => {
+			let (start, end) = err.span().byte_range(text);
+			let add_attr = |attr_opt: Option<pango::Attribute>| {
+				if let Some(mut attr) = attr_opt {
+					attr.set_start_index(start as u32);
+					attr.set_end_index(end as u32);
+					attrs.insert(attr);
+				}
+			};
+			add_attr(pango::Attribute::new_underline(pango::Underline::Error));
+			add_attr(pango::Attribute::new_foreground(0x8888, 0, 0));
+			let msg = err.to_string();
+			entry.set_icon_from_icon_name(gtk::EntryIconPosition::Secondary, "dialog-error-symbolic");
+			entry.set_icon_tooltip_text(gtk::EntryIconPosition::Secondary, msg.as_str());
+		},
+		None => {
+			entry.set_icon_from_icon_name(gtk::EntryIconPosition::Secondary, None::<&str>);
+		}
+	}
+	entry.set_attributes(&attrs);
+}
+
 fn build_ui(app: &gtk::Application, app_args: &AppArgs) {
 	let window = ApplicationWindow::new(app);
+	let settings = Rc::new(RefCell::new(storage::load_settings()));
+	let registry = Rc::new(RefCell::new(Registry::new()));
+	let user_funcs = storage::load_user_funcs(&settings.borrow(), &registry.borrow());
+	registry.borrow_mut().user_funcs = user_funcs;
 
 	let header = Header::new();
 	window.set_titlebar(&header.header_bar);
 	window.set_wmclass("dkalc", "Dkalc");
-	window.set_default_size(350, 100);
+	window.set_default_size(settings.borrow().window_width, settings.borrow().window_height);
 
 	////////////////////////////////////////////////////////////////
 	let gtk_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
@@ -137,17 +431,39 @@ fn build_ui(app: &gtk::Application, app_args: &AppArgs) {
 
 	let label_result_dec = gtk::Label::new(Some("0"));
 	gtk::WidgetExt::set_name(&label_result_dec, "result");
-	gtk_box.pack_start(&label_result_dec, true, true, 0);
+	let row_dec = new_result_row(&label_result_dec, "win.copy-dec", "Copy (Ctrl+Shift+D)");
+	gtk_box.pack_start(&row_dec, true, true, 0);
 
 	let label_result_hex = gtk::Label::new(Some("0x0"));
 	gtk::WidgetExt::set_name(&label_result_hex, "result");
-	gtk_box.pack_start(&label_result_hex, true, true, 0);
+	let row_hex = new_result_row(&label_result_hex, "win.copy-hex", "Copy (Ctrl+Shift+C)");
+	gtk_box.pack_start(&row_hex, true, true, 0);
+
+	let label_result_char = gtk::Label::new(Some("--"));
+	gtk::WidgetExt::set_name(&label_result_char, "result");
+	let row_char = new_result_row(&label_result_char, "win.copy-char", "Copy");
+	gtk_box.pack_start(&row_char, true, true, 0);
+
+	let label_result_bin = gtk::Label::new(Some("0b0"));
+	gtk::WidgetExt::set_name(&label_result_bin, "result");
+	label_result_bin.set_line_wrap(true);
+	label_result_bin.set_line_wrap_mode(pango::WrapMode::Char);
+	let row_bin = new_result_row(&label_result_bin, "win.copy-bin", "Copy (Ctrl+Shift+B)");
+	gtk_box.pack_start(&row_bin, true, true, 0);
+
+	let copy_actions = vec![
+		new_copy_action(&window, "copy-dec", &label_result_dec),
+		new_copy_action(&window, "copy-hex", &label_result_hex),
+		new_copy_action(&window, "copy-char", &label_result_char),
+		new_copy_action(&window, "copy-bin", &label_result_bin)
+	];
+	app.set_accels_for_action("win.copy-dec", &["<Primary><Shift>d"]);
+	app.set_accels_for_action("win.copy-hex", &["<Primary><Shift>c"]);
+	app.set_accels_for_action("win.copy-bin", &["<Primary><Shift>b"]);
 
 	// CSS
 	let css_provider = gtk::CssProvider::new();
-	let css = "#state { color: #800; } #result { font-family: monospace; font-size: 15px; }";
-	if let Err(err) = css_provider.load_from_data(css.as_bytes()) {
-		println!("css_provider.load_from_data failed: {}", err);
+	if !load_css(&css_provider, &settings.borrow()) {
 		return;
 	}
 	gtk::StyleContext::add_provider_for_screen(
@@ -158,23 +474,151 @@ fn build_ui(app: &gtk::Application, app_args: &AppArgs) {
 
 	// Input box
 	let entry = gtk::Entry::new();
+
+	// Bits of the result, for programmers
+	let bit_panel = bit_panel::BitPanel::new(&entry);
+	gtk_box.pack_start(&bit_panel.grid, true, true, 0);
+
 	gtk_box.pack_start(&entry, true, true, 0);
 
+	// Function names are completed, with a description below the input box
+	let func_completion = completion::FuncCompletion::new(&entry, registry.clone());
+	gtk_box.pack_start(&func_completion.label_help, false, false, 0);
+
+	// History, below the input box
+	let history_panel = HistoryPanel::new(&entry, settings.clone(), registry.clone());
+	gtk_box.pack_start(&history_panel.scrolled_window, true, true, 0);
+
+	let result_view = Rc::new(ResultView {
+		entry: entry.clone(),
+		label_state,
+		label_result_dec,
+		label_result_hex,
+		label_result_char,
+		label_result_bin,
+		row_dec,
+		row_hex,
+		row_char,
+		row_bin,
+		copy_actions,
+		bit_panel,
+		settings: settings.clone(),
+		registry: registry.clone(),
+		error_span: Cell::new(None),
+		debug_mode: app_args.debug_mode
+	});
+
 	entry.connect_changed({
-		let debug_mode = app_args.debug_mode;
+		let result_view = result_view.clone();
+		move |_| { result_view.update(); }
+	});
+
+	// Everything that uses the user functions is refreshed when they change
+	let on_funcs_changed: Rc<dyn Fn()> = Rc::new({
+		let registry = registry.clone();
+		let result_view = result_view.clone();
+		let history_panel = history_panel.clone();
+		move || {
+			storage::save_user_funcs(&registry.borrow().user_funcs);
+			func_completion.refresh();
+			result_view.update();
+			HistoryPanel::reevaluate(&history_panel);
+		}
+	});
+
+	// Enter defines a function, or keeps the current expression in the history
+	entry.connect_activate({
+		let settings = settings.clone();
+		let registry = registry.clone();
+		let history_panel = history_panel.clone();
+		let on_funcs_changed = on_funcs_changed.clone();
 		move |arg| {
-			if let Some(str) = arg.get_chars(0, -1) {
-				let de = eval::eval_input_debug_detailed(&str, debug_mode);
-				label_state.set_label(&de.state_str);
-				label_result_dec.set_label(&de.result_dec);
-				label_result_hex.set_label(&de.result_hex);
+			let text = match arg.get_text() {
+				Some(text) => text,
+				None => return
+			};
+			let definition = eval::parse_definition(&text, &settings.borrow(), &registry.borrow());
+			if let Ok(Some(func)) = definition {
+				registry.borrow_mut().user_funcs.define(func);
+				arg.set_text("");
+				on_funcs_changed();
+			}
+			else if HistoryPanel::commit(&history_panel, &text) {
+				arg.select_region(0, -1);
+			}
+		}
+	});
+
+	// Pasted numbers often come from code or documents
+	entry.connect_paste_clipboard(|arg| {
+		paste_cleaned(arg);
+		arg.stop_signal_emission("paste-clipboard");
+	});
+
+	// Clicking the error icon selects the faulty part of the input
+	entry.connect_icon_press({
+		let result_view = result_view.clone();
+		move |arg, _, _| {
+			if let Some(span) = result_view.error_span.get() {
+				arg.grab_focus();
+				if span.start == span.end {
+					arg.select_region(span.start as i32, span.start as i32);
+					arg.set_position(span.start as i32);
+				}
+				else {
+					arg.select_region(span.start as i32, span.end as i32);
+				}
 			}
 		}
 	});
 
 	entry.set_text(&app_args.expression);
 
+	// Save the session when closing
+	window.connect_delete_event({
+		let settings = settings.clone();
+		let history_panel = history_panel.clone();
+		move |arg, _| {
+			let (width, height) = arg.get_size();
+			settings.borrow_mut().window_width = width;
+			settings.borrow_mut().window_height = height;
+			storage::save_settings(&settings.borrow());
+			storage::save_history(&history_panel.history.borrow());
+			Inhibit(false)
+		}
+	});
+
 	window.show_all();
+	result_view.update();
+
+	// Everything that depends on the settings is refreshed when they change
+	let on_settings_changed: Rc<dyn Fn()> = Rc::new({
+		let settings = settings.clone();
+		move || {
+			load_css(&css_provider, &settings.borrow());
+			result_view.update();
+			HistoryPanel::reevaluate(&history_panel);
+			storage::save_settings(&settings.borrow());
+		}
+	});
+
+	let sa_prefs = SimpleAction::new("preferences", None);
+	window.add_action(&sa_prefs);
+	sa_prefs.connect_activate({
+		let window = window.clone();
+		move |_, _| {
+			preferences::show_preferences(&window, settings.clone(), on_settings_changed.clone());
+		}
+	});
+
+	let sa_funcs = SimpleAction::new("functions", None);
+	window.add_action(&sa_funcs);
+	sa_funcs.connect_activate({
+		let window = window.clone();
+		move |_, _| {
+			function_list::show_function_list(&window, registry.clone(), on_funcs_changed.clone());
+		}
+	});
 
 	let sa = SimpleAction::new("about", None);
 	window.add_action(&sa);
@@ -0,0 +1,262 @@
+use gtk;
+use gtk::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use big_dec::Rounding;
+use settings::{AngleUnit, Settings};
+use format::NumberStyle;
+
+// Changes the settings then tells the rest of the application
+fn modify<F: Fn(&mut Settings)>(settings: &Rc<RefCell<Settings>>, on_change: &Rc<dyn Fn()>, update: F) {
+	update(&mut settings.borrow_mut());
+	on_change();
+}
+
+fn attach_row<W: IsA<gtk::Widget>>(grid: &gtk::Grid, row: i32, text: &str, widget: &W) {
+	let label = gtk::Label::new(Some(text));
+	label.set_xalign(0.0);
+	grid.attach(&label, 0, row, 1, 1);
+	grid.attach(widget, 1, row, 1, 1);
+}
+
+fn new_spin_button(min: usize, max: usize, value: usize) -> gtk::SpinButton {
+	let spin_button = gtk::SpinButton::new_with_range(min as f64, max as f64, 1.0);
+	spin_button.set_value(value as f64);
+	spin_button
+}
+
+fn new_combo_box(items: &[(&str, &str)], active_id: &str) -> gtk::ComboBoxText {
+	let combo_box = gtk::ComboBoxText::new();
+	for &(id, text) in items {
+		combo_box.append(Some(id), text);
+	}
+	combo_box.set_active_id(Some(active_id));
+	combo_box
+}
+
+// Opens the preferences window. Every modification is applied
+// immediately and on_change is called after it.
+pub fn show_preferences(parent: &gtk::ApplicationWindow, settings: Rc<RefCell<Settings>>,
+	on_change: Rc<dyn Fn()>) {
+
+	let current = settings.borrow().clone();
+
+	let window = gtk::Window::new(gtk::WindowType::Toplevel);
+	window.set_title("Preferences");
+	window.set_transient_for(Some(parent));
+	window.set_destroy_with_parent(true);
+	window.set_modal(true);
+	window.set_resizable(false);
+
+	let grid = gtk::Grid::new();
+	grid.set_row_spacing(6);
+	grid.set_column_spacing(12);
+	grid.set_border_width(12);
+	window.add(&grid);
+
+	// Digits
+	let frac_digits = new_spin_button(0, 20, current.frac_digits);
+	attach_row(&grid, 0, "Fractional digits", &frac_digits);
+	frac_digits.connect_property_value_notify({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_value_as_int() as usize;
+			modify(&settings, &on_change, |s| s.frac_digits = val);
+		}
+	});
+
+	let int_digits = new_spin_button(1, 20, current.int_digits);
+	attach_row(&grid, 1, "Integer digits", &int_digits);
+	int_digits.connect_property_value_notify({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_value_as_int() as usize;
+			modify(&settings, &on_change, |s| s.int_digits = val);
+		}
+	});
+
+	let rounding_id = match current.rounding {
+		Rounding::HalfUp => "half_up",
+		Rounding::HalfEven => "half_even",
+		Rounding::Down => "down"
+	};
+	let rounding = new_combo_box(&[
+		("half_up", "Half up"),
+		("half_even", "Half even"),
+		("down", "Toward zero")], rounding_id);
+	attach_row(&grid, 2, "Rounding", &rounding);
+	rounding.connect_changed({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = match arg.get_active_id() {
+				Some(ref id) if id == "half_even" => Rounding::HalfEven,
+				Some(ref id) if id == "down" => Rounding::Down,
+				_ => Rounding::HalfUp
+			};
+			modify(&settings, &on_change, |s| s.rounding = val);
+		}
+	});
+
+	// Angles and words
+	let angle_id = match current.angle_unit {
+		AngleUnit::Radian => "rad",
+		AngleUnit::Degree => "deg"
+	};
+	let angle_unit = new_combo_box(&[("rad", "Radians"), ("deg", "Degrees")], angle_id);
+	attach_row(&grid, 3, "Angle unit", &angle_unit);
+	angle_unit.connect_changed({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = match arg.get_active_id() {
+				Some(ref id) if id == "deg" => AngleUnit::Degree,
+				_ => AngleUnit::Radian
+			};
+			modify(&settings, &on_change, |s| s.angle_unit = val);
+		}
+	});
+
+	let word_size = new_combo_box(&[
+		("8", "8 bits"),
+		("16", "16 bits"),
+		("32", "32 bits"),
+		("64", "64 bits")], &current.word_size.to_string());
+	attach_row(&grid, 4, "Word size", &word_size);
+	word_size.connect_changed({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			if let Some(val) = arg.get_active_id().and_then(|id| id.parse::<u32>().ok()) {
+				modify(&settings, &on_change, |s| s.word_size = val);
+			}
+		}
+	});
+
+	// Display
+	let show_dec = gtk::CheckButton::new_with_label("Decimal");
+	show_dec.set_active(current.show_dec);
+	attach_row(&grid, 5, "Results", &show_dec);
+	show_dec.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.show_dec = val);
+		}
+	});
+
+	let show_hex = gtk::CheckButton::new_with_label("Hexadecimal");
+	show_hex.set_active(current.show_hex);
+	grid.attach(&show_hex, 1, 6, 1, 1);
+	show_hex.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.show_hex = val);
+		}
+	});
+
+	let show_bin = gtk::CheckButton::new_with_label("Binary");
+	show_bin.set_active(current.show_bin);
+	grid.attach(&show_bin, 1, 7, 1, 1);
+	show_bin.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.show_bin = val);
+		}
+	});
+
+	let show_char = gtk::CheckButton::new_with_label("Character");
+	show_char.set_active(current.show_char);
+	grid.attach(&show_char, 1, 8, 1, 1);
+	show_char.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.show_char = val);
+		}
+	});
+
+	let show_bits = gtk::CheckButton::new_with_label("Bits");
+	show_bits.set_active(current.show_bits);
+	grid.attach(&show_bits, 1, 9, 1, 1);
+	show_bits.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.show_bits = val);
+		}
+	});
+
+	let group_digits = gtk::CheckButton::new_with_label("Group digits");
+	group_digits.set_active(current.group_digits);
+	grid.attach(&group_digits, 1, 10, 1, 1);
+	group_digits.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.group_digits = val);
+		}
+	});
+
+	let magnitude_suffix = gtk::CheckButton::new_with_label("Magnitude suffixes (1.5M, 64Ki)");
+	magnitude_suffix.set_active(current.magnitude_suffix);
+	grid.attach(&magnitude_suffix, 1, 11, 1, 1);
+	magnitude_suffix.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.magnitude_suffix = val);
+		}
+	});
+
+	let number_style = new_combo_box(&[
+		("locale", "From the locale"),
+		("plain", "1_234.5"),
+		("english", "1,234.5"),
+		("european", "1.234,5"),
+		("french", "1\u{202f}234,5"),
+		("swiss", "1'234.5")], current.number_style.name());
+	attach_row(&grid, 12, "Number format", &number_style);
+	number_style.connect_changed({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			if let Some(val) = arg.get_active_id().and_then(|id| NumberStyle::from_name(&id)) {
+				modify(&settings, &on_change, |s| s.number_style = val);
+			}
+		}
+	});
+
+	// Input
+	let implicit_mul = gtk::CheckButton::new_with_label("Implicit multiplication");
+	implicit_mul.set_active(current.implicit_mul);
+	attach_row(&grid, 13, "Input", &implicit_mul);
+	implicit_mul.connect_toggled({
+		let settings = settings.clone();
+		let on_change = on_change.clone();
+		move |arg| {
+			let val = arg.get_active();
+			modify(&settings, &on_change, |s| s.implicit_mul = val);
+		}
+	});
+
+	let font_size = new_spin_button(6, 72, current.font_size as usize);
+	attach_row(&grid, 14, "Font size", &font_size);
+	font_size.connect_property_value_notify(move |arg| {
+		let val = arg.get_value_as_int();
+		modify(&settings, &on_change, |s| s.font_size = val);
+	});
+
+	window.show_all();
+}
@@ -0,0 +1,185 @@
+use std;
+use big_dec::Rounding;
+use format::NumberStyle;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum AngleUnit {
+	Radian,
+	Degree
+}
+
+// User preferences. They are stored as "key = value" lines, see
+// to_text() for the list of keys.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Settings {
+	pub frac_digits: usize, // max number of fractional digits displayed
+	pub int_digits: usize, // results with more integer digits are reported as overflow
+	pub rounding: Rounding, // applied to the displayed results
+	pub angle_unit: AngleUnit,
+	pub word_size: u32, // in bits, used to display negative values in hexadecimal
+	pub show_dec: bool,
+	pub show_hex: bool,
+	pub show_bin: bool,
+	pub show_char: bool, // the character of the code, or the text of the bytes
+	pub show_bits: bool, // panel to toggle the bits of the result
+	pub group_digits: bool,
+	pub number_style: NumberStyle, // separators of decimal numbers, also accepted in the input
+	pub implicit_mul: bool, // 2pi and 3(4+5) are products
+	pub magnitude_suffix: bool, // results are written like 1.5M or 64Ki
+	pub font_size: i32, // of the results, in pixels
+	pub window_width: i32,
+	pub window_height: i32
+}
+
+impl Settings {
+	pub fn new() -> Settings {
+		Settings {
+			frac_digits: 20,
+			int_digits: 20,
+			rounding: Rounding::HalfUp,
+			angle_unit: AngleUnit::Radian,
+			word_size: 32,
+			show_dec: true,
+			show_hex: true,
+			show_bin: false,
+			show_char: false,
+			show_bits: false,
+			group_digits: false,
+			number_style: NumberStyle::Locale,
+			implicit_mul: true,
+			magnitude_suffix: false,
+			font_size: 15,
+			window_width: 350,
+			window_height: 100
+		}
+	}
+
+	pub fn to_text(&self) -> String {
+		let angle_unit = match self.angle_unit {
+			AngleUnit::Radian => "rad",
+			AngleUnit::Degree => "deg"
+		};
+		let rounding = match self.rounding {
+			Rounding::HalfUp => "half_up",
+			Rounding::HalfEven => "half_even",
+			Rounding::Down => "down"
+		};
+		let mut ret = String::new();
+		ret.push_str(&format!("frac_digits = {}\n", self.frac_digits));
+		ret.push_str(&format!("int_digits = {}\n", self.int_digits));
+		ret.push_str(&format!("rounding = {}\n", rounding));
+		ret.push_str(&format!("angle_unit = {}\n", angle_unit));
+		ret.push_str(&format!("word_size = {}\n", self.word_size));
+		ret.push_str(&format!("show_dec = {}\n", self.show_dec));
+		ret.push_str(&format!("show_hex = {}\n", self.show_hex));
+		ret.push_str(&format!("show_bin = {}\n", self.show_bin));
+		ret.push_str(&format!("show_char = {}\n", self.show_char));
+		ret.push_str(&format!("show_bits = {}\n", self.show_bits));
+		ret.push_str(&format!("group_digits = {}\n", self.group_digits));
+		ret.push_str(&format!("number_style = {}\n", self.number_style.name()));
+		ret.push_str(&format!("implicit_mul = {}\n", self.implicit_mul));
+		ret.push_str(&format!("magnitude_suffix = {}\n", self.magnitude_suffix));
+		ret.push_str(&format!("font_size = {}\n", self.font_size));
+		ret.push_str(&format!("window_width = {}\n", self.window_width));
+		ret.push_str(&format!("window_height = {}\n", self.window_height));
+		ret
+	}
+
+	// Reads settings written by to_text(). Unknown keys and invalid
+	// values are ignored so that a damaged file gives default values
+	// instead of an error.
+	pub fn from_text(text: &str) -> Settings {
+		let mut ret = Settings::new();
+		for line in text.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut parts = line.splitn(2, '=');
+			let key = parts.next().unwrap_or("").trim();
+			let value = match parts.next() {
+				Some(value) => value.trim(),
+				None => continue
+			};
+			ret.set_value(key, value);
+		}
+		ret
+	}
+
+	fn set_value(&mut self, key: &str, value: &str) {
+		match key {
+			"frac_digits" => set_parsed(&mut self.frac_digits, value, |v| v <= 20),
+			"int_digits" => set_parsed(&mut self.int_digits, value, |v| v >= 1 && v <= 20),
+			"rounding" => match value {
+				"half_up" => self.rounding = Rounding::HalfUp,
+				"half_even" => self.rounding = Rounding::HalfEven,
+				"down" => self.rounding = Rounding::Down,
+				_ => ()
+			},
+			"angle_unit" => match value {
+				"rad" => self.angle_unit = AngleUnit::Radian,
+				"deg" => self.angle_unit = AngleUnit::Degree,
+				_ => ()
+			},
+			"word_size" => set_parsed(&mut self.word_size, value,
+				|v| v == 8 || v == 16 || v == 32 || v == 64),
+			"show_dec" => set_parsed(&mut self.show_dec, value, |_| true),
+			"show_hex" => set_parsed(&mut self.show_hex, value, |_| true),
+			"show_bin" => set_parsed(&mut self.show_bin, value, |_| true),
+			"show_char" => set_parsed(&mut self.show_char, value, |_| true),
+			"show_bits" => set_parsed(&mut self.show_bits, value, |_| true),
+			"group_digits" => set_parsed(&mut self.group_digits, value, |_| true),
+			"number_style" => if let Some(style) = NumberStyle::from_name(value) {
+				self.number_style = style;
+			},
+			"implicit_mul" => set_parsed(&mut self.implicit_mul, value, |_| true),
+			"magnitude_suffix" => set_parsed(&mut self.magnitude_suffix, value, |_| true),
+			"font_size" => set_parsed(&mut self.font_size, value, |v| v >= 6 && v <= 72),
+			"window_width" => set_parsed(&mut self.window_width, value, |v| v > 0 && v < 10000),
+			"window_height" => set_parsed(&mut self.window_height, value, |v| v > 0 && v < 10000),
+			_ => ()
+		}
+	}
+}
+
+// Changes the value only if the text is valid
+fn set_parsed<T, F>(target: &mut T, text: &str, is_valid: F)
+	where T: std::str::FromStr + Copy, F: Fn(T) -> bool
+{
+	if let Ok(val) = text.parse::<T>() {
+		if is_valid(val) {
+			*target = val;
+		}
+	}
+}
+
+#[test]
+fn test_settings_round_trip() {
+	let mut settings = Settings::new();
+	settings.frac_digits = 4;
+	settings.int_digits = 12;
+	settings.rounding = Rounding::HalfEven;
+	settings.group_digits = true;
+	settings.font_size = 20;
+	settings.angle_unit = AngleUnit::Degree;
+	settings.word_size = 16;
+	settings.show_hex = false;
+	settings.show_bits = true;
+	settings.show_bin = true;
+	settings.show_char = true;
+	settings.number_style = NumberStyle::French;
+	settings.implicit_mul = false;
+	settings.magnitude_suffix = true;
+	settings.window_width = 500;
+	assert_eq!(settings, Settings::from_text(&settings.to_text()));
+}
+
+#[test]
+fn test_settings_damaged() {
+	let text = "word_size = 12\nfrac_digits = 3\n\u{0}garbage\nangle_unit=deg\nshow_dec = maybe\n";
+	let settings = Settings::from_text(text);
+	assert_eq!(32, settings.word_size);
+	assert_eq!(3, settings.frac_digits);
+	assert_eq!(AngleUnit::Degree, settings.angle_unit);
+	assert_eq!(true, settings.show_dec);
+}
@@ -0,0 +1,154 @@
+// Saves and restores the session in plain text files:
+//
+//   $XDG_CONFIG_HOME/dkalc/settings.conf   preferences, see Settings::to_text()
+//   $XDG_DATA_HOME/dkalc/history.txt       history, see History::to_text()
+//   $XDG_DATA_HOME/dkalc/functions.txt     user functions, see UserFuncs::to_text()
+//
+// Each file starts with a header line "dkalc <kind> <version>", for
+// instance "dkalc settings 1". Readers skip what they do not understand,
+// so a damaged or newer file never prevents dkalc from starting: the
+// default values are used instead.
+use std;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use settings::Settings;
+use history::History;
+use user_funcs::UserFuncs;
+use funcs::Registry;
+
+const FORMAT_VERSION: u32 = 1;
+
+// $XDG_xxx_HOME/dkalc, or $HOME/<fallback>/dkalc
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+	let base = match env::var_os(var) {
+		Some(ref dir) if PathBuf::from(dir).is_absolute() => PathBuf::from(dir),
+		_ => {
+			let home = match env::var_os("HOME") {
+				Some(home) => home,
+				None => return None
+			};
+			let mut dir = PathBuf::from(home);
+			dir.push(fallback);
+			dir
+		}
+	};
+	Some(base.join("dkalc"))
+}
+
+fn config_path(file_name: &str) -> Option<PathBuf> {
+	xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(file_name))
+}
+
+fn data_path(file_name: &str) -> Option<PathBuf> {
+	xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join(file_name))
+}
+
+fn make_header(kind: &str) -> String {
+	format!("dkalc {} {}\n", kind, FORMAT_VERSION)
+}
+
+// Returns the content that follows the header, if the header is the expected one
+fn strip_header<'a>(text: &'a str, kind: &str) -> Option<&'a str> {
+	let mut parts = text.splitn(2, '\n');
+	let header = parts.next().unwrap_or("");
+	let body = parts.next().unwrap_or("");
+	let words: Vec<&str> = header.split_whitespace().collect();
+	if words.len() != 3 || words[0] != "dkalc" || words[1] != kind {
+		return None;
+	}
+	match words[2].parse::<u32>() {
+		Ok(_) => Some(body),
+		Err(_) => None
+	}
+}
+
+fn read_file(path: Option<PathBuf>, kind: &str) -> Option<String> {
+	let path = match path {
+		Some(path) => path,
+		None => return None
+	};
+	let text = match fs::read(&path) {
+		Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+		Err(err) => {
+			if err.kind() != std::io::ErrorKind::NotFound {
+				eprintln!("cannot read {}: {}", path.display(), err);
+			}
+			return None;
+		}
+	};
+	match strip_header(&text, kind) {
+		Some(body) => Some(body.to_string()),
+		None => {
+			eprintln!("ignoring {}: bad header", path.display());
+			None
+		}
+	}
+}
+
+// Writes to a temporary file first so that a crash cannot leave
+// a truncated file behind
+fn write_file(path: Option<PathBuf>, kind: &str, body: &str) {
+	let path = match path {
+		Some(path) => path,
+		None => return
+	};
+	let res = (|| -> std::io::Result<()> {
+		if let Some(dir) = path.parent() {
+			try!(fs::create_dir_all(dir));
+		}
+		let tmp_path = path.with_extension("tmp");
+		{
+			let mut file = try!(fs::File::create(&tmp_path));
+			try!(file.write_all(make_header(kind).as_bytes()));
+			try!(file.write_all(body.as_bytes()));
+		}
+		fs::rename(&tmp_path, &path)
+	})();
+	if let Err(err) = res {
+		eprintln!("cannot write {}: {}", path.display(), err);
+	}
+}
+
+pub fn load_settings() -> Settings {
+	match read_file(config_path("settings.conf"), "settings") {
+		Some(body) => Settings::from_text(&body),
+		None => Settings::new()
+	}
+}
+
+pub fn save_settings(settings: &Settings) {
+	write_file(config_path("settings.conf"), "settings", &settings.to_text());
+}
+
+pub fn load_history(settings: &Settings, registry: &Registry) -> History {
+	match read_file(data_path("history.txt"), "history") {
+		Some(body) => History::from_text(&body, settings, registry),
+		None => History::new()
+	}
+}
+
+pub fn save_history(history: &History) {
+	write_file(data_path("history.txt"), "history", &history.to_text());
+}
+
+pub fn load_user_funcs(settings: &Settings, registry: &Registry) -> UserFuncs {
+	match read_file(data_path("functions.txt"), "functions") {
+		Some(body) => UserFuncs::from_text(&body, settings, registry),
+		None => UserFuncs::new()
+	}
+}
+
+pub fn save_user_funcs(user_funcs: &UserFuncs) {
+	write_file(data_path("functions.txt"), "functions", &user_funcs.to_text());
+}
+
+#[test]
+fn test_strip_header() {
+	assert_eq!(Some("a = 1\n"), strip_header("dkalc settings 1\na = 1\n", "settings"));
+	assert_eq!(Some(""), strip_header("dkalc history 7", "history"));
+	assert_eq!(None, strip_header("dkalc history 1\n1+2\n", "settings"));
+	assert_eq!(None, strip_header("dkalc settings x\n", "settings"));
+	assert_eq!(None, strip_header("", "settings"));
+}
@@ -1,10 +1,17 @@
 use std;
 use big_dec;
 use big_dec::BigDec;
+use error;
+use dates;
+use error::{ErrorKind, Span};
+use format::Separators;
 
-const MAX_NAME_LEN: u8 = 12;
+// Longest name, chosen so that a Name is not bigger than a BigDec
+// and tokens stay cheap to copy
+const MAX_NAME_LEN: u8 = 31;
 
-// Stores a small function name
+// Stores a function or variable name: ascii letters, digits and
+// underscores, not starting with a digit
 #[derive(Copy,Clone)]
 pub struct Name {
 	len: u8,
@@ -12,10 +19,24 @@ pub struct Name {
 }
 
 impl Name {
-	pub fn to_string(&self) -> String {
+	// Name holding any short ascii text, such as the name of a combined
+	// unit like km/h
+	pub fn from_str(text: &str) -> Option<Name> {
+		if !text.is_ascii() || text.len() > MAX_NAME_LEN as usize {
+			return None;
+		}
+		let mut name = Name { len: text.len() as u8, bytes: [0;MAX_NAME_LEN as usize] };
+		name.bytes[..text.len()].copy_from_slice(text.as_bytes());
+		Some(name)
+	}
+
+	pub fn as_str(&self) -> &str {
 		let slice = &self.bytes[0..self.len as usize];
-		let s = std::str::from_utf8(slice).unwrap();
-		String::from(s)
+		std::str::from_utf8(slice).unwrap()
+	}
+
+	pub fn to_string(&self) -> String {
+		String::from(self.as_str())
 	}
 }
 
@@ -24,6 +45,9 @@ impl Name {
 pub enum Token {
 	Nothing,
 	Number(BigDec),
+	Date(BigDec), // 2026-10-17 or 2026-10-17T08:30, in seconds since the Unix epoch
+	Duration(BigDec), // 1h30m, in seconds, made by the parser from a number and the name after it
+	Str(usize), // "123456789", index of the text in the tree, 0 when tokenizing
 	ParOpen,
 	ParClose,
 	Add,
@@ -32,8 +56,31 @@ pub enum Token {
 	Div,
 	Mod,
 	Func(Name), // Ends with a ParClose
+	Var(Name), // Name not followed by a parenthesis, such as a function parameter
+	Comma, // Separates function arguments
+	Assign, // Defines a function: f(x) = x + 1
 	Fact,
-	And
+	DoubleFact, // Made by the parser from two Fact after their operand
+	And,
+	Eq,
+	NotEq,
+	Less,
+	LessEq,
+	Greater,
+	GreaterEq,
+	LogicAnd,
+	LogicOr,
+	Not, // Made by the parser from a Fact before its operand
+	Question, // cond ? a : b, also made by the parser from if(cond, a, b)
+	Colon,
+	Convert // 3 MiB in KiB, also written with to
+}
+
+// A token and the range of characters it comes from
+#[derive(Copy,Clone)]
+pub struct TokenSpan {
+	pub token: Token,
+	pub span: Span
 }
 
 impl Token {
@@ -41,6 +88,9 @@ impl Token {
 		match *self {
 			Token::Nothing => "_".into(),
 			Token::Number(ref nv) => nv.to_string().into(),
+			Token::Date(ref secs) => dates::format_date(*secs).into(),
+			Token::Duration(ref secs) => dates::format_duration(*secs).into(),
+			Token::Str(_) => "\"\"".into(),
 			Token::ParOpen => "(".into(),
 			Token::ParClose => ")".into(),
 			Token::Add => "+".into(),
@@ -53,8 +103,33 @@ impl Token {
 				name_par.push_str("(");
 				name_par.into()
 			},
+			Token::Var(ref name) => name.to_string().into(),
+			Token::Comma => ",".into(),
+			Token::Assign => "=".into(),
 			Token::Fact => "!".into(),
-			Token::And => "&".into()
+			Token::DoubleFact => "!!".into(),
+			Token::And => "&".into(),
+			Token::Eq => "==".into(),
+			Token::NotEq => "!=".into(),
+			Token::Less => "<".into(),
+			Token::LessEq => "<=".into(),
+			Token::Greater => ">".into(),
+			Token::GreaterEq => ">=".into(),
+			Token::LogicAnd => "&&".into(),
+			Token::LogicOr => "||".into(),
+			Token::Not => "!".into(),
+			Token::Question => "?".into(),
+			Token::Colon => ":".into(),
+			Token::Convert => "in".into()
+		}
+	}
+
+	// Operators giving 1 for true and 0 for false
+	pub fn is_boolean(&self) -> bool {
+		match *self {
+			Token::Eq | Token::NotEq | Token::Less | Token::LessEq | Token::Greater
+				| Token::GreaterEq | Token::LogicAnd | Token::LogicOr | Token::Not => true,
+			_ => false
 		}
 	}
 }
@@ -63,8 +138,10 @@ pub enum Error {
 	Nothing, // End of string, or nothing found
 	BadChar(char),
 	BadNum(big_dec::Error),
-	FuncTooLong,
-	FuncMissingPar
+	BadDate,
+	BadCharLiteral,
+	BadString,
+	NameTooLong
 }
 
 impl Error {
@@ -73,77 +150,262 @@ impl Error {
 			Error::Nothing => "".into(),
 			Error::BadChar(c) => format!("bad char: '{}'", c),
 			Error::BadNum(ref nverr) => nverr.to_string(),
-			Error::FuncTooLong => "function name too long".into(),
-			Error::FuncMissingPar => "missing function parenthesis".into(),
+			Error::BadDate => "invalid date".into(),
+			Error::BadCharLiteral => "invalid character literal".into(),
+			Error::BadString => "invalid string literal".into(),
+			Error::NameTooLong => format!("name too long, the limit is {} characters", MAX_NAME_LEN),
+		}
+	}
+
+	fn kind(&self) -> ErrorKind {
+		match *self {
+			Error::Nothing => ErrorKind::BadChar,
+			Error::BadChar(_) => ErrorKind::BadChar,
+			Error::BadNum(ref nverr) => ErrorKind::from_big_dec(nverr),
+			Error::BadDate => ErrorKind::BadDate,
+			Error::BadCharLiteral => ErrorKind::BadCharLiteral,
+			Error::BadString => ErrorKind::BadString,
+			Error::NameTooLong => ErrorKind::NameTooLong,
 		}
 	}
 }
 
-// Parses a function, made of a name (of type Name) followed immediately by an open parenthesis
-fn parse_func(input_chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Name, Error> {
+fn is_name_start(c: char) -> bool {
+	c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Parses a name (of type Name). Followed immediately by an open
+// parenthesis, this is a function, otherwise this is a variable.
+fn parse_name<I: Iterator<Item = char>>(input_chars: &mut std::iter::Peekable<I>) -> Result<Token, Error> {
 	let mut name = Name { len: 0, bytes: [0;MAX_NAME_LEN as usize] };
-	let mut wants_first_letter = true;
 	loop {
-		let c = {
-			let c_opt = input_chars.peek();
-			if c_opt.is_none() {
-				return Err(Error::Nothing); // End of string
-			}
-			let c = c_opt.unwrap();
-			*c
+		let c = match input_chars.peek() {
+			Some(c) => *c,
+			None => break // End of string
 		};
 
-		if wants_first_letter {
-			if 'a' <= c && c <= 'z' {
-				// This is a function name
-				wants_first_letter = false;
-				name.bytes[name.len as usize] = c as u8; // We only handle ascii so casting to u8 is ok
-				name.len += 1;
-			}
-			else {
-				// Not a function, this is not an error
-				return Err(Error::Nothing);
+		if is_name_start(c) || (name.len > 0 && is_name_char(c)) {
+			if name.len == MAX_NAME_LEN {
+				// Name too long
+				return Err(Error::NameTooLong);
 			}
+			name.bytes[name.len as usize] = c as u8; // We only handle ascii so casting to u8 is ok
+			name.len += 1;
+		}
+		else if c == '(' && name.len > 0 {
+			// Function. Consume current char before leaving.
+			input_chars.next();
+			return Ok(Token::Func(name));
 		}
 		else {
-			if 'a' <= c && c <= 'z' {
-				if name.len == MAX_NAME_LEN {
-					// Name too long
-					return Err(Error::FuncTooLong);
-				}
-				name.bytes[name.len as usize] = c as u8; // We only handle ascii so casting to u8 is ok
-				name.len += 1;
-			}
-			else if c == '(' {
-				// Done. Consume current char before leaving.
-				input_chars.next();
-				break;
-			}
-			else {
-			    return Err(Error::FuncMissingPar);
-			}
-
+			break;
 		}
 		input_chars.next();
 	}
-	Ok(name)
+	if name.len == 0 {
+		// Not a name, this is not an error
+		return Err(Error::Nothing);
+	}
+	match name.as_str() {
+		"in" | "to" => Ok(Token::Convert),
+		_ => Ok(Token::Var(name))
+	}
 }
 
+// Char of an escape sequence such as \n or \u{263A}, the backslash
+// being already read. None if the sequence is not valid.
+fn parse_escape<I: Iterator<Item = char>>(input_chars: &mut std::iter::Peekable<I>) -> Option<char> {
+	match input_chars.next() {
+		Some('n') => Some('\n'),
+		Some('r') => Some('\r'),
+		Some('t') => Some('\t'),
+		Some('0') => Some('\0'),
+		Some('\\') => Some('\\'),
+		Some('\'') => Some('\''),
+		Some('"') => Some('"'),
+		Some('u') => parse_unicode_escape(input_chars),
+		_ => None
+	}
+}
+
+// The {263A} of \u{263A}
+fn parse_unicode_escape<I: Iterator<Item = char>>(input_chars: &mut std::iter::Peekable<I>) -> Option<char> {
+	if input_chars.next() != Some('{') {
+		return None;
+	}
+	let mut digits = String::new();
+	while let Some(c) = input_chars.next() {
+		if c == '}' {
+			return u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32);
+		}
+		if !c.is_ascii_hexdigit() || digits.len() == 6 {
+			break;
+		}
+		digits.push(c);
+	}
+	None
+}
+
+// Text of a string literal, quotes included, that the tokenizer accepted
+pub fn string_value(literal: &str) -> String {
+	let mut input_chars = literal.chars().peekable();
+	input_chars.next(); // opening quote
+	let mut ret = String::new();
+	while let Some(c) = input_chars.next() {
+		match c {
+			'"' => break,
+			'\\' => ret.extend(parse_escape(&mut input_chars)),
+			_ => ret.push(c)
+		}
+	}
+	ret
+}
+
+// Chars of the input, counting how many are left as they are read so
+// that the position of the tokenizer is known without counting again
+#[derive(Clone)]
+struct InputChars<'a> {
+	chars: std::str::Chars<'a>,
+	left: usize
+}
+
+impl<'a> Iterator for InputChars<'a> {
+	type Item = char;
+
+	fn next(&mut self) -> Option<char> {
+		let ret = self.chars.next();
+		if ret.is_some() {
+			self.left -= 1;
+		}
+		ret
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.left, Some(self.left))
+	}
+}
+
+impl<'a> ExactSizeIterator for InputChars<'a> {}
+
 struct InputContext<'a> {
-	input_chars: std::iter::Peekable<std::str::Chars<'a>>
+	input_chars: std::iter::Peekable<InputChars<'a>>,
+	input_len: usize, // in chars
+	start: usize, // position of the first char of the current token
+	separators: Separators, // accepted in decimal numbers
+	depth: usize // of parentheses
 }
 
 impl<'a> InputContext<'a> {
-	fn new(input: &str) -> InputContext {
-		let ic = input.chars().peekable();
-		InputContext { input_chars: ic }
+	fn new(input: &str, separators: Separators) -> InputContext {
+		let input_len = input.chars().count();
+		let ic = InputChars { chars: input.chars(), left: input_len }.peekable();
+		InputContext { input_chars: ic, input_len, start: 0, separators, depth: 0 }
+	}
+
+	// Separators to use for the next number. Inside parentheses a comma
+	// may separate function arguments, so it does not group digits. A
+	// decimal comma is kept, the arguments being then separated by ';'.
+	fn number_separators(&self) -> (char, char) {
+		let group = if self.separators.group == ',' && self.depth > 0 { '_' } else { self.separators.group };
+		(self.separators.decimal, group)
+	}
+
+	// Consumes the next char if it is the expected one
+	fn next_is(&mut self, expected: char) -> bool {
+		if self.input_chars.peek() == Some(&expected) {
+			self.input_chars.next();
+			return true;
+		}
+		false
+	}
+
+	// Seconds since the Unix epoch of the ISO date that starts here, if
+	// any: 2026-10-17, 2026-10-17T08:30 or 2026-10-17T08:30:15
+	fn parse_date(&mut self) -> Result<Option<BigDec>, Error> {
+		let text: Vec<char> = self.input_chars.clone().take(19).collect();
+		let matches = |pattern: &str| {
+			pattern.len() <= text.len() && pattern.chars().zip(text.iter()).all(|(p, &c)| {
+				if p == '9' { c.is_ascii_digit() } else { p == c }
+			})
+		};
+		if !matches("9999-99-99") {
+			return Ok(None);
+		}
+		let len = if matches("9999-99-99T99:99:99") {
+			19
+		}
+		else if matches("9999-99-99T99:99") {
+			16
+		}
+		else {
+			10
+		};
+		let field = |start: usize, end: usize| {
+			if end > len {
+				return 0;
+			}
+			text[start..end].iter().collect::<String>().parse::<i128>().unwrap()
+		};
+		let secs = dates::to_unix(field(0, 4), field(5, 7), field(8, 10), field(11, 13), field(14, 16), field(17, 19));
+		for _ in 0..len {
+			self.input_chars.next();
+		}
+		match secs {
+			Some(secs) => Ok(Some(secs)),
+			None => Err(Error::BadDate)
+		}
+	}
+
+	// Code point of a character literal such as 'A', '\n' or '\u{263A}',
+	// the opening quote being already read
+	fn parse_char_literal(&mut self) -> Result<BigDec, Error> {
+		let c = match self.input_chars.next() {
+			Some('\\') => try!(parse_escape(&mut self.input_chars).ok_or(Error::BadCharLiteral)),
+			Some('\'') | None => return Err(Error::BadCharLiteral),
+			Some(c) => c
+		};
+		if !self.next_is('\'') {
+			return Err(Error::BadCharLiteral);
+		}
+		Ok(BigDec::from_i32(c as i32))
+	}
+
+	// Checks a string literal such as "abc\n", the opening quote being
+	// already read. Its text is decoded by string_value().
+	fn parse_string_literal(&mut self) -> Result<Token, Error> {
+		while let Some(c) = self.input_chars.next() {
+			match c {
+				'"' => return Ok(Token::Str(0)),
+				'\\' => { try!(parse_escape(&mut self.input_chars).ok_or(Error::BadString)); },
+				_ => ()
+			}
+		}
+		Err(Error::BadString) // not closed
+	}
+
+	// Position of the next char to read, a peeked char being still to read
+	fn pos(&self) -> usize {
+		self.input_len - self.input_chars.len()
 	}
 
 	fn next_token(&mut self) -> Result<Token, Error> {
 		let ret;
 		loop {
+			self.start = self.pos();
+
+			// Try to parse a date, before the number that starts it
+			if let Some(secs) = try!(self.parse_date()) {
+				ret = Ok(Token::Date(secs));
+				break;
+			}
+
 			// Try to parse a number
-			let num_res = BigDec::parse_chars(&mut self.input_chars);
+			let (decimal, group) = self.number_separators();
+			let num_res = BigDec::parse_chars_sep(&mut self.input_chars, decimal, group);
 			match num_res {
 				Ok(num) => {
 					ret = Ok(Token::Number(num));
@@ -157,11 +419,14 @@ impl<'a> InputContext<'a> {
 				}
 			}
 
-			// Try to parse a function
-			let func_name_res = parse_func(&mut self.input_chars);
-			match func_name_res {
-				Ok(func_name) => {
-					ret = Ok(Token::Func(func_name));
+			// Try to parse a function or a variable
+			let name_res = parse_name(&mut self.input_chars);
+			match name_res {
+				Ok(token) => {
+					if let Token::Func(_) = token {
+						self.depth += 1;
+					}
+					ret = Ok(token);
 					break;
 				},
 				Err(err) => {
@@ -180,10 +445,12 @@ impl<'a> InputContext<'a> {
 			}
 			let c = c_opt.unwrap();
 			if c == '(' {
+				self.depth += 1;
 				ret = Ok(Token::ParOpen);
 				break;
 			}
 			else if c == ')' {
+				self.depth = self.depth.saturating_sub(1);
 				ret = Ok(Token::ParClose);
 				break;
 			}
@@ -208,11 +475,51 @@ impl<'a> InputContext<'a> {
 				break;
 			}
 			else if c == '!' {
-				ret = Ok(Token::Fact);
+				// Factorial or logical not, depending on where it is
+				ret = Ok(if self.next_is('=') { Token::NotEq } else { Token::Fact });
 				break;
 			}
 			else if c == '&' {
-				ret = Ok(Token::And);
+				ret = Ok(if self.next_is('&') { Token::LogicAnd } else { Token::And });
+				break;
+			}
+			else if c == '|' {
+				if !self.next_is('|') {
+					return Err(Error::BadChar(c));
+				}
+				ret = Ok(Token::LogicOr);
+				break;
+			}
+			else if c == '<' {
+				ret = Ok(if self.next_is('=') { Token::LessEq } else { Token::Less });
+				break;
+			}
+			else if c == '>' {
+				ret = Ok(if self.next_is('=') { Token::GreaterEq } else { Token::Greater });
+				break;
+			}
+			else if c == ',' || (c == ';' && self.separators.decimal == ',') {
+				ret = Ok(Token::Comma);
+				break;
+			}
+			else if c == '\'' {
+				ret = self.parse_char_literal().map(Token::Number);
+				break;
+			}
+			else if c == '"' {
+				ret = self.parse_string_literal();
+				break;
+			}
+			else if c == '?' {
+				ret = Ok(Token::Question);
+				break;
+			}
+			else if c == ':' {
+				ret = Ok(Token::Colon);
+				break;
+			}
+			else if c == '=' {
+				ret = Ok(if self.next_is('=') { Token::Eq } else { Token::Assign });
 				break;
 			}
 			else if c == ' ' {
@@ -227,17 +534,34 @@ impl<'a> InputContext<'a> {
 	}
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+#[allow(dead_code)]
+pub fn tokenize(input: &str) -> Result<Vec<TokenSpan>, error::Error> {
+	tokenize_sep(input, Separators { decimal: '.', group: '_' })
+}
+
+// Same as tokenize(), also accepting the given separators in decimal numbers
+pub fn tokenize_sep(input: &str, separators: Separators) -> Result<Vec<TokenSpan>, error::Error> {
 	let mut ret = Vec::new();
-	let mut context = InputContext::new(input);
+	let mut context = InputContext::new(input, separators);
 	loop {
 		let token_res = context.next_token();
 		match token_res {
-			Ok(token) => ret.push(token),
+			Ok(token) => {
+				let span = Span::new(context.start, context.pos());
+				ret.push(TokenSpan { token, span });
+			},
 			Err(err) => {
 				match err {
 					Error::Nothing => (),
-					_ => { return Err(err); }
+					_ => {
+						// Include the faulty char if it was not consumed
+						let mut end = context.pos();
+						if end == context.start && end < context.input_len {
+							end += 1;
+						}
+						let span = Span::new(context.start, end);
+						return Err(error::Error::new(err.kind(), span, err.to_string()));
+					}
 				}
 				break;
 			}
@@ -245,3 +569,284 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
 	}
 	Ok(ret)
 }
+
+// Name being typed before the char position pos, with the position of
+// its first char. Used to complete function names.
+pub fn name_before(text: &str, pos: usize) -> Option<(usize, String)> {
+	let chars: Vec<char> = text.chars().take(pos).collect();
+	let mut start = chars.len();
+	while start > 0 && is_name_char(chars[start - 1]) {
+		start -= 1;
+	}
+	// Letters after a digit belong to a number, like in 0xab
+	if start == chars.len() || !is_name_start(chars[start]) {
+		return None;
+	}
+	Some((start, chars[start..].iter().collect()))
+}
+
+// Name of the innermost function whose arguments contain the char
+// position pos, if any
+pub fn func_at(text: &str, pos: usize) -> Option<String> {
+	let mut calls: Vec<Option<String>> = Vec::new(); // None for plain parentheses
+	let mut name = String::new();
+	let mut in_number = false; // letters in numbers are digits, like in 0xab
+	for c in text.chars().take(pos) {
+		if in_number && c.is_alphanumeric() {
+			continue;
+		}
+		in_number = false;
+		if is_name_start(c) || (!name.is_empty() && is_name_char(c)) {
+			name.push(c);
+			continue;
+		}
+		if c.is_digit(10) && name.is_empty() {
+			in_number = true;
+		}
+		else if c == '(' {
+			calls.push(if name.is_empty() { None } else { Some(name.clone()) });
+		}
+		else if c == ')' {
+			calls.pop();
+		}
+		name.clear();
+	}
+	calls.into_iter().rev().filter_map(|call| call).next()
+}
+
+// Rewrites numbers pasted from elsewhere so that the tokenizer
+// understands them: "1,234,567" -> "1234567", "0x10UL" -> "0x10",
+// "$FF" -> "0xFF", and the Unicode minus sign becomes '-'. A lone u
+// after a decimal number is kept since it is the micro suffix: 250u.
+// Commas inside parentheses are kept since they may separate
+// function arguments.
+pub fn clean_pasted(text: &str) -> String {
+	let chars: Vec<char> = text.trim().chars().collect();
+	let is_digit_at = |i: usize, radix: u32| i < chars.len() && chars[i].is_digit(radix);
+	let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+
+	let mut ret = String::with_capacity(chars.len());
+	let mut depth = 0;
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		let after_ident = i > 0 && is_ident_char(chars[i - 1]);
+
+		if c == '\u{2212}' {
+			ret.push('-');
+			i += 1;
+			continue;
+		}
+		if c == '(' {
+			depth += 1;
+		}
+		else if c == ')' && depth > 0 {
+			depth -= 1;
+		}
+
+		let hex_start = if c == '$' && is_digit_at(i + 1, 16) {
+			Some(i + 1)
+		}
+		else if c == '0' && !after_ident && i + 2 < chars.len()
+			&& (chars[i + 1] == 'x' || chars[i + 1] == 'X') && is_digit_at(i + 2, 16) {
+			Some(i + 2)
+		}
+		else {
+			None
+		};
+
+		if let Some(start) = hex_start {
+			// Hexadecimal literal
+			ret.push_str("0x");
+			i = start;
+			while i < chars.len() && (chars[i].is_digit(16) || chars[i] == '_') {
+				ret.push(chars[i]);
+				i += 1;
+			}
+		}
+		else if c.is_digit(10) && !after_ident {
+			// Decimal literal, possibly with thousands separators
+			let mut group_len = 0;
+			let mut grouped = false;
+			while i < chars.len() {
+				let c = chars[i];
+				if c.is_digit(10) {
+					group_len += 1;
+				}
+				else if c == ',' && depth == 0
+					&& (if grouped { group_len == 3 } else { group_len >= 1 && group_len <= 3 })
+					&& is_digit_at(i + 1, 10) && is_digit_at(i + 2, 10) && is_digit_at(i + 3, 10)
+					&& !is_digit_at(i + 4, 10) {
+					grouped = true;
+					group_len = 0;
+					i += 1;
+					continue;
+				}
+				else if c == '.' || c == '_' || (c == 'b' && ret.ends_with('0') && group_len == 1) {
+					// Fractional part, separator or binary prefix: keep as is
+				}
+				else {
+					break;
+				}
+				ret.push(c);
+				i += 1;
+			}
+		}
+		else {
+			ret.push(c);
+			i += 1;
+			continue;
+		}
+
+		// Drop C integer suffixes: u, l, ul, ll, ull...
+		let mut j = i;
+		while j < chars.len() && j - i < 3 && "uUlL".contains(chars[j]) {
+			j += 1;
+		}
+		let lone_u = j == i + 1 && (chars[i] == 'u' || chars[i] == 'U');
+		if j > i && !(j < chars.len() && is_ident_char(chars[j])) && (hex_start.is_some() || !lone_u) {
+			i = j;
+		}
+	}
+	ret
+}
+
+#[test]
+fn test_tokenize_spans() {
+	let tokens = tokenize("12 + sqrt(4)").ok().unwrap();
+	assert_eq!(5, tokens.len());
+	assert_eq!(Span::new(0, 2), tokens[0].span);
+	assert_eq!(Span::new(3, 4), tokens[1].span);
+	assert_eq!(Span::new(5, 10), tokens[2].span);
+	assert_eq!(Span::new(10, 11), tokens[3].span);
+	assert_eq!(Span::new(11, 12), tokens[4].span);
+
+	// Positions count chars, not bytes
+	let tokens = tokenize("'é' + 10").ok().unwrap();
+	assert_eq!(Span::new(0, 3), tokens[0].span);
+	assert_eq!(Span::new(6, 8), tokens[2].span);
+}
+
+#[test]
+fn test_tokenize_error_span() {
+	let err = tokenize("1 + 2 $ 3").err().unwrap();
+	assert_eq!(ErrorKind::BadChar, err.kind());
+	assert_eq!(Span::new(6, 7), err.span());
+}
+
+#[test]
+fn test_tokenize_separators() {
+	let separators = Separators { decimal: ',', group: '.' };
+	let tokens = tokenize_sep("1,5 + 2", separators).ok().unwrap();
+	assert_eq!(3, tokens.len());
+	assert_eq!("1.5", tokens[0].token.to_string());
+
+	let tokens = tokenize_sep("1.234.567,5", separators).ok().unwrap();
+	assert_eq!(1, tokens.len());
+	assert_eq!("1234567.5", tokens[0].token.to_string());
+
+	// Arguments are then separated by ';'
+	let tokens = tokenize_sep("f(1,5)", separators).ok().unwrap();
+	assert_eq!(3, tokens.len());
+	assert_eq!("1.5", tokens[1].token.to_string());
+	let tokens = tokenize_sep("f(1;5)", separators).ok().unwrap();
+	assert_eq!(5, tokens.len());
+	assert_eq!(",", tokens[2].token.to_string());
+	assert_eq!(ErrorKind::BadChar, tokenize("f(1;5)").err().unwrap().kind());
+
+	// Inside parentheses an English group comma is left to separate arguments
+	let tokens = tokenize_sep("f(1,500)", Separators { decimal: '.', group: ',' }).ok().unwrap();
+	assert_eq!(5, tokens.len());
+}
+
+#[test]
+fn test_tokenize_definition() {
+	let tokens = tokenize("f(x, y) = x*y").ok().unwrap();
+	let texts: Vec<String> = tokens.iter().map(|ts| ts.token.to_string().into_owned()).collect();
+	assert_eq!(vec!["f(", "x", ",", "y", ")", "=", "x", "*", "y"], texts);
+}
+
+#[test]
+fn test_tokenize_comparisons() {
+	let tokens = tokenize("a<=b != !c&&d || 3! == 6 & 4").ok().unwrap();
+	let texts: Vec<String> = tokens.iter().map(|ts| ts.token.to_string().into_owned()).collect();
+	assert_eq!(vec!["a", "<=", "b", "!=", "!", "c", "&&", "d", "||", "3", "!", "==", "6", "&", "4"], texts);
+	assert_eq!(Span::new(1, 3), tokens[1].span);
+	assert_eq!(ErrorKind::BadChar, tokenize("1 | 2").err().unwrap().kind());
+	assert_eq!(5, tokenize("x?1:2").ok().unwrap().len());
+}
+
+#[test]
+fn test_tokenize_char_literals() {
+	let swiss = Separators { decimal: '.', group: '\'' };
+	let tokens = tokenize_sep("1'234 + 'A'", swiss).ok().unwrap();
+	let texts: Vec<String> = tokens.iter().map(|ts| ts.token.to_string().into_owned()).collect();
+	assert_eq!(vec!["1234", "+", "65"], texts);
+	assert_eq!(Span::new(8, 11), tokens[2].span);
+	let err = tokenize("'\\q'").err().unwrap();
+	assert_eq!(ErrorKind::BadCharLiteral, err.kind());
+	assert_eq!("invalid character literal", err.to_string());
+	assert_eq!(Span::new(0, 3), err.span());
+}
+
+#[test]
+fn test_tokenize_strings() {
+	let tokens = tokenize("crc32(\"a, b\")").ok().unwrap();
+	assert_eq!(3, tokens.len());
+	assert_eq!(Span::new(6, 12), tokens[1].span);
+	assert_eq!("a, b", string_value("\"a, b\""));
+	assert_eq!("\u{263A}\"\n", string_value("\"\\u{263A}\\\"\\n\""));
+	assert_eq!("", string_value("\"\""));
+	let err = tokenize("crc32(\"abc").err().unwrap();
+	assert_eq!(ErrorKind::BadString, err.kind());
+	assert_eq!(Span::new(6, 10), err.span());
+	assert_eq!(ErrorKind::BadString, tokenize("\"\\q\"").err().unwrap().kind());
+}
+
+#[test]
+fn test_tokenize_names() {
+	let texts = |input: &str| -> Vec<String> {
+		tokenize(input).ok().unwrap().iter().map(|ts| ts.token.to_string().into_owned()).collect()
+	};
+	assert_eq!(vec!["popcount64(", "x", ")"], texts("popcount64(x)"));
+	assert_eq!(vec!["To_Celsius(", "_t2", ")"], texts("To_Celsius(_t2)"));
+	assert_eq!(vec!["171", "+", "ab"], texts("0xab + ab"));
+	let long_name = "a".repeat(40);
+	assert_eq!(ErrorKind::NameTooLong, tokenize(&long_name).err().unwrap().kind());
+}
+
+#[test]
+fn test_name_before() {
+	assert_eq!(Some((4, "sq".to_string())), name_before("1 + sq", 6));
+	assert_eq!(Some((0, "co".to_string())), name_before("cos(1)", 2));
+	assert_eq!(None, name_before("1 + ", 4));
+	assert_eq!(None, name_before("0xab", 4));
+	assert_eq!(Some((2, "to_c".to_string())), name_before("2+to_c", 6));
+}
+
+#[test]
+fn test_func_at() {
+	assert_eq!(Some("sqrt".to_string()), func_at("sqrt(1 + (2", 11));
+	assert_eq!(Some("cos".to_string()), func_at("sqrt(cos(1", 10));
+	assert_eq!(Some("sqrt".to_string()), func_at("sqrt(cos(1) + 2", 15));
+	assert_eq!(None, func_at("sqrt(2) + 1", 9));
+	assert_eq!(None, func_at("(1 + 2", 6));
+	assert_eq!(None, func_at("0xab(", 5));
+	assert_eq!(Some("crc32".to_string()), func_at("crc32(1", 7));
+}
+
+#[test]
+fn test_clean_pasted() {
+	assert_eq!("1234567", clean_pasted("1,234,567"));
+	assert_eq!("1234567.5 + 2", clean_pasted(" 1,234,567.5 + 2\n"));
+	assert_eq!("12,34", clean_pasted("12,34"));
+	assert_eq!("1234,5678", clean_pasted("1234,5678"));
+	assert_eq!("gcd(12,345)", clean_pasted("gcd(12,345)"));
+	assert_eq!("0x10 + 10", clean_pasted("0x10u + 10UL"));
+	assert_eq!("0xFF & 0x0f", clean_pasted("$FF & 0X0fULL"));
+	assert_eq!("-3 - 4", clean_pasted("\u{2212}3 \u{2212} 4"));
+	assert_eq!("0b101 * 2", clean_pasted("0b101 * 2l"));
+	assert_eq!("crc32(1)", clean_pasted("crc32(1)"));
+	assert_eq!("250u + 0x10", clean_pasted("250u + 0x10u"));
+	assert_eq!("250 + 3", clean_pasted("250ul + 3ULL"));
+}
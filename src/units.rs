@@ -0,0 +1,555 @@
+use big_dec;
+use big_dec::BigDec;
+use token::Name;
+use dates;
+
+// Exponents of the base dimensions: length, time, data size, temperature
+#[derive(Copy,Clone,Debug,PartialEq)]
+struct Dims([i8; 4]);
+
+const NO_DIMS: Dims = Dims([0, 0, 0, 0]);
+const LENGTH: Dims = Dims([1, 0, 0, 0]);
+const TIME: Dims = Dims([0, 1, 0, 0]);
+const FREQUENCY: Dims = Dims([0, -1, 0, 0]);
+const DATA: Dims = Dims([0, 0, 1, 0]);
+const TEMPERATURE: Dims = Dims([0, 0, 0, 1]);
+
+impl Dims {
+	// Dimensions of a product, or of a quotient when sign is -1
+	fn combine(a: Dims, b: Dims, sign: i8) -> Dims {
+		let mut ret = a;
+		for i in 0..ret.0.len() {
+			ret.0[i] += sign * b.0[i];
+		}
+		ret
+	}
+}
+
+#[derive(Copy,Clone,PartialEq)]
+enum Prefixes {
+	None,
+	Si,      // k, M, m, u...
+	SiBinary // and Ki, Mi...
+}
+
+struct BaseUnit {
+	name: &'static str,
+	scale: &'static str, // size in base units
+	offset: &'static str, // base value of the zero of the unit
+	dims: Dims,
+	prefixes: Prefixes
+}
+
+// Temperatures are kept in degrees Rankine so that all the scales are
+// exact decimals
+const BASE_UNITS: [BaseUnit; 11] = [
+	BaseUnit { name: "m", scale: "1", offset: "0", dims: LENGTH, prefixes: Prefixes::Si },
+	BaseUnit { name: "s", scale: "1", offset: "0", dims: TIME, prefixes: Prefixes::Si },
+	BaseUnit { name: "min", scale: "60", offset: "0", dims: TIME, prefixes: Prefixes::None },
+	BaseUnit { name: "h", scale: "3600", offset: "0", dims: TIME, prefixes: Prefixes::None },
+	BaseUnit { name: "day", scale: "86400", offset: "0", dims: TIME, prefixes: Prefixes::None },
+	BaseUnit { name: "Hz", scale: "1", offset: "0", dims: FREQUENCY, prefixes: Prefixes::Si },
+	BaseUnit { name: "B", scale: "1", offset: "0", dims: DATA, prefixes: Prefixes::SiBinary },
+	BaseUnit { name: "bit", scale: "0.125", offset: "0", dims: DATA, prefixes: Prefixes::SiBinary },
+	BaseUnit { name: "K", scale: "1.8", offset: "0", dims: TEMPERATURE, prefixes: Prefixes::None },
+	BaseUnit { name: "degC", scale: "1.8", offset: "491.67", dims: TEMPERATURE, prefixes: Prefixes::None },
+	BaseUnit { name: "degF", scale: "1", offset: "459.67", dims: TEMPERATURE, prefixes: Prefixes::None },
+];
+
+const SI_PREFIXES: [(&str, &str); 9] = [
+	("P", "1000000000000000"),
+	("T", "1000000000000"),
+	("G", "1000000000"),
+	("M", "1000000"),
+	("k", "1000"),
+	("m", "0.001"),
+	("u", "0.000001"),
+	("n", "0.000000001"),
+	("p", "0.000000000001"),
+];
+
+const BINARY_PREFIXES: [(&str, &str); 5] = [
+	("Ki", "1024"),
+	("Mi", "1048576"),
+	("Gi", "1073741824"),
+	("Ti", "1099511627776"),
+	("Pi", "1125899906842624"),
+];
+
+// Quantities of time that are shown in their own way
+#[derive(Copy,Clone,PartialEq)]
+enum Kind {
+	Plain,
+	Date, // seconds since the Unix epoch, shown as 2026-10-17
+	Duration // seconds, shown as 1h30m
+}
+
+pub enum Error {
+	Incompatible(String, String), // names of the units
+	NotPlain(String), // name of the unit
+	NameTooLong,
+	Op(big_dec::Error)
+}
+
+impl Error {
+	pub fn to_string(&self) -> String {
+		match *self {
+			Error::Incompatible(ref left, ref right) => format!("incompatible units: {} and {}", left, right),
+			Error::NotPlain(ref name) => format!("expected a number without unit, found {}", name),
+			Error::NameTooLong => "unit name too long".to_string(),
+			Error::Op(ref bd_err) => bd_err.to_string()
+		}
+	}
+}
+
+impl From<big_dec::Error> for Error {
+	fn from(err: big_dec::Error) -> Error {
+		Error::Op(err)
+	}
+}
+
+// Unit of a quantity, simple such as km or combined such as km/h.
+// Its size in base units is scale_num/scale_den, kept as a fraction so
+// that km/h to m/s stays exact.
+#[derive(Copy,Clone)]
+pub struct Unit {
+	name: Name,
+	scale_num: BigDec,
+	scale_den: BigDec,
+	offset: BigDec, // for temperatures
+	dims: Dims,
+	kind: Kind
+}
+
+impl Unit {
+	pub fn name(&self) -> &str {
+		self.name.as_str()
+	}
+
+	// Whether quantities of this unit are shown as dates or durations
+	// instead of a number and the unit name
+	pub fn is_formatted(&self) -> bool {
+		self.kind != Kind::Plain
+	}
+
+	// The second, named date or s to tell how to show the value
+	fn second(kind: Kind) -> Unit {
+		let name = if kind == Kind::Date { "date" } else { "s" };
+		Unit { name: Name::from_str(name).unwrap(), kind, ..find("s").unwrap() }
+	}
+}
+
+fn parse(text: &str) -> BigDec {
+	BigDec::parse_str(text).unwrap()
+}
+
+// Unit with this name, such as "km", "KiB" or "degC". The long names
+// also have a plural: days, mins, bits.
+pub fn find(name: &str) -> Option<Unit> {
+	match find_singular(name) {
+		Some(unit) => Some(unit),
+		None if name.len() > 3 && name.ends_with('s') => {
+			let singular = &name[..name.len() - 1];
+			BASE_UNITS.iter().find(|base| base.name.len() >= 3 && base.name == singular)
+				.and_then(|_| find_singular(singular))
+				.map(|unit| Unit { name: Name::from_str(name).unwrap(), ..unit })
+		},
+		None => None
+	}
+}
+
+fn find_singular(name: &str) -> Option<Unit> {
+	for base in BASE_UNITS.iter() {
+		if !name.ends_with(base.name) {
+			continue;
+		}
+		let prefix = &name[..name.len() - base.name.len()];
+		let prefix_scale = if prefix.is_empty() {
+			"1"
+		}
+		else {
+			let mut prefixes: Vec<&(&str, &str)> = Vec::new();
+			if base.prefixes != Prefixes::None {
+				prefixes.extend(SI_PREFIXES.iter());
+			}
+			if base.prefixes == Prefixes::SiBinary {
+				prefixes.extend(BINARY_PREFIXES.iter());
+			}
+			match prefixes.iter().find(|&&&(text, _)| text == prefix) {
+				Some(&&(_, scale)) => scale,
+				None => continue
+			}
+		};
+		return Some(Unit {
+			name: Name::from_str(name).unwrap(),
+			scale_num: BigDec::mul(parse(prefix_scale), parse(base.scale)).unwrap(),
+			scale_den: BigDec::from_i32(1),
+			offset: parse(base.offset),
+			dims: base.dims,
+			kind: Kind::Plain
+		});
+	}
+	None
+}
+
+pub fn is_unit(name: &str) -> bool {
+	find(name).is_some()
+}
+
+// Scale of a magnitude suffix written right after a number, such as
+// the k of 4k or the Mi of 16Mi
+pub fn magnitude(suffix: &str) -> Option<BigDec> {
+	SI_PREFIXES.iter().chain(BINARY_PREFIXES.iter())
+		.find(|&&(text, _)| text == suffix)
+		.map(|&(_, scale)| parse(scale))
+}
+
+// The value divided by the magnitude that fits it best, and the suffix
+// to write after it. Binary suffixes are used when they divide the value
+// exactly, like 64Ki for 65536, otherwise the SI suffix giving a number
+// from 1 to 999 is used, like 1.5M. None when no suffix is needed.
+pub fn best_magnitude(value: BigDec) -> Option<(BigDec, &'static str)> {
+	let one = BigDec::from_i32(1);
+	let fits = |quotient: BigDec, limit: i32| {
+		BigDec::compare(quotient, one) >= 0 && BigDec::compare(quotient, BigDec::from_i32(limit)) < 0
+	};
+	if value.is_zero() || fits(value, 1000) {
+		return None;
+	}
+	for &(suffix, scale) in BINARY_PREFIXES.iter() {
+		if let Ok(quotient) = BigDec::div(value, parse(scale)) {
+			if quotient.is_integer() && fits(quotient, 1024) {
+				return Some((quotient, suffix));
+			}
+		}
+	}
+	for &(suffix, scale) in SI_PREFIXES.iter() {
+		if let Ok(quotient) = BigDec::div(value, parse(scale)) {
+			if fits(quotient, 1000) {
+				return Some((quotient, suffix));
+			}
+		}
+	}
+	None
+}
+
+// Simple units of a unit name with their exponents, such as
+// [("km", 1), ("h", -1)] for km/h or [("m", 2)] for m^2
+fn name_factors(name: &str) -> Vec<(&str, i32)> {
+	let mut parts = name.splitn(2, '/');
+	let num = parts.next().unwrap();
+	let den = parts.next().unwrap_or("").trim_start_matches('(').trim_end_matches(')');
+	let mut ret = Vec::new();
+	for (text, sign) in num.split('*').map(|text| (text, 1)).chain(den.split('*').map(|text| (text, -1))) {
+		if text.is_empty() || text == "1" {
+			continue;
+		}
+		let mut pieces = text.splitn(2, '^');
+		let factor = pieces.next().unwrap();
+		let exponent = pieces.next().and_then(|exp| exp.parse::<i32>().ok()).unwrap_or(1);
+		ret.push((factor, sign * exponent));
+	}
+	ret
+}
+
+// Name of a combined unit, such as km/h. The exponents of the simple
+// units add up, so that m^2*m is m^3 and m/s/s is m/s^2.
+fn combined_name(left: &Unit, right: &Unit, op: char) -> Result<Name, Error> {
+	let sign = if op == '/' { -1 } else { 1 };
+	let mut factors = name_factors(left.name());
+	for (factor, exponent) in name_factors(right.name()) {
+		match factors.iter().position(|&(other, _)| other == factor) {
+			Some(pos) => factors[pos].1 += sign * exponent,
+			None => factors.push((factor, sign * exponent))
+		}
+	}
+	let power = |&(factor, exponent): &(&str, i32)| match exponent.abs() {
+		1 => factor.to_string(),
+		exponent => format!("{}^{}", factor, exponent)
+	};
+	let num: Vec<String> = factors.iter().filter(|factor| factor.1 > 0).map(&power).collect();
+	let den: Vec<String> = factors.iter().filter(|factor| factor.1 < 0).map(&power).collect();
+	let mut text = if num.is_empty() { "1".to_string() } else { num.join("*") };
+	if den.len() == 1 {
+		text = format!("{}/{}", text, den[0]);
+	}
+	else if den.len() > 1 {
+		text = format!("{}/({})", text, den.join("*"));
+	}
+	Name::from_str(&text).ok_or(Error::NameTooLong)
+}
+
+// Value in base units of 1 left unit divided by the one of 1 right
+// unit, offsets ignored
+fn scale_ratio(left: &Unit, right: &Unit) -> Result<(BigDec, BigDec), Error> {
+	Ok((try!(BigDec::mul(left.scale_num, right.scale_den)), try!(BigDec::mul(left.scale_den, right.scale_num))))
+}
+
+// val * num / den, multiplying first to keep the digits
+fn mul_div(val: BigDec, num: BigDec, den: BigDec) -> Result<BigDec, Error> {
+	Ok(try!(BigDec::div(try!(BigDec::mul(val, num)), den)))
+}
+
+// A number with an optional unit, what expressions evaluate to
+#[derive(Copy,Clone)]
+pub struct Quantity {
+	pub value: BigDec,
+	pub unit: Option<Unit>
+}
+
+impl Quantity {
+	pub fn plain(value: BigDec) -> Quantity {
+		Quantity { value, unit: None }
+	}
+
+	// Point in time, secs being the seconds since the Unix epoch
+	pub fn date(secs: BigDec) -> Quantity {
+		Quantity { value: secs, unit: Some(Unit::second(Kind::Date)) }
+	}
+
+	pub fn duration(secs: BigDec) -> Quantity {
+		Quantity { value: secs, unit: Some(Unit::second(Kind::Duration)) }
+	}
+
+	pub fn is_date(&self) -> bool {
+		self.unit.map_or(false, |unit| unit.kind == Kind::Date)
+	}
+
+	fn incompatible(left: &Quantity, right: &Quantity) -> Error {
+		Error::Incompatible(left.unit_name().to_string(), right.unit_name().to_string())
+	}
+
+	pub fn unit_name(&self) -> &str {
+		match self.unit {
+			Some(ref unit) => unit.name(),
+			None => "no unit"
+		}
+	}
+
+	// The value, for the operations that do not accept units
+	pub fn to_plain(&self) -> Result<BigDec, Error> {
+		match self.unit {
+			Some(ref unit) => Err(Error::NotPlain(unit.name().to_string())),
+			None => Ok(self.value)
+		}
+	}
+
+	pub fn to_string(&self) -> String {
+		match self.unit {
+			Some(Unit { kind: Kind::Date, .. }) => dates::format_date(self.value),
+			Some(Unit { kind: Kind::Duration, .. }) => dates::format_duration(self.value),
+			Some(ref unit) => format!("{} {}", self.value.to_string(), unit.name()),
+			None => self.value.to_string()
+		}
+	}
+
+	// Values of both quantities in the unit of the left one, to add
+	// them. Temperatures are taken as differences: in 20 degC + 9 degF
+	// the right one is a rise of 9 degF, so the sum is 25 degC.
+	pub fn common_values(left: Quantity, right: Quantity) -> Result<(BigDec, BigDec, Option<Unit>), Error> {
+		let incompatible = || Quantity::incompatible(&left, &right);
+		if left.is_date() != right.is_date() {
+			return Err(incompatible());
+		}
+		match (left.unit, right.unit) {
+			(None, None) => Ok((left.value, right.value, None)),
+			(Some(left_unit), Some(right_unit)) => {
+				if left_unit.dims != right_unit.dims {
+					return Err(incompatible());
+				}
+				let (num, den) = try!(scale_ratio(&right_unit, &left_unit));
+				Ok((left.value, try!(mul_div(right.value, num, den)), Some(left_unit)))
+			},
+			_ => Err(incompatible())
+		}
+	}
+
+	// Values of both quantities in the unit of the left one, to compare
+	// them. Temperatures are taken as points of their scale, so that
+	// 0 degC equals 32 degF.
+	pub fn compared_values(left: Quantity, right: Quantity) -> Result<(BigDec, BigDec), Error> {
+		let (left_value, right_value, unit) = try!(Quantity::common_values(left, right));
+		match (unit, right.unit) {
+			(Some(unit), Some(right_unit)) if !unit.offset.is_zero() || !right_unit.offset.is_zero() => {
+				let one = Quantity { value: BigDec::from_i32(1), unit: Some(unit) };
+				Ok((left_value, try!(right.convert(one)).value))
+			},
+			_ => Ok((left_value, right_value))
+		}
+	}
+
+	pub fn add(left: Quantity, right: Quantity) -> Result<Quantity, Error> {
+		Quantity::sum(left, right, false)
+	}
+
+	pub fn sub(left: Quantity, right: Quantity) -> Result<Quantity, Error> {
+		Quantity::sum(left, right, true)
+	}
+
+	// A date moves by a time quantity, and two dates are apart by a
+	// duration
+	fn sum(left: Quantity, right: Quantity, subtract: bool) -> Result<Quantity, Error> {
+		let op = |a: BigDec, b: BigDec| if subtract { BigDec::sub(a, b) } else { BigDec::add(a, b) };
+		let secs = |time: Quantity| time.convert(Quantity { value: BigDec::from_i32(1), unit: find("s") })
+			.map(|secs| secs.value)
+			.map_err(|_| Quantity::incompatible(&left, &right));
+		match (left.is_date(), right.is_date()) {
+			(false, false) => {
+				let (left_value, right_value, unit) = try!(Quantity::common_values(left, right));
+				Ok(Quantity { value: try!(op(left_value, right_value)), unit })
+			},
+			(true, false) => Ok(Quantity { value: try!(op(left.value, try!(secs(right)))), ..left }),
+			(false, true) if !subtract => Ok(Quantity { value: try!(op(try!(secs(left)), right.value)), ..right }),
+			(true, true) if subtract => Ok(Quantity::duration(try!(op(left.value, right.value)))),
+			_ => Err(Quantity::incompatible(&left, &right))
+		}
+	}
+
+	pub fn mul(left: Quantity, right: Quantity) -> Result<Quantity, Error> {
+		if left.is_date() || right.is_date() {
+			return Err(Quantity::incompatible(&left, &right));
+		}
+		let value = try!(BigDec::mul(left.value, right.value));
+		let (left_unit, right_unit) = match (left.unit, right.unit) {
+			(Some(left_unit), Some(right_unit)) => (left_unit, right_unit),
+			(unit, None) | (None, unit) => return Ok(Quantity { value, unit })
+		};
+		let dims = Dims::combine(left_unit.dims, right_unit.dims, 1);
+		let scale_num = try!(BigDec::mul(left_unit.scale_num, right_unit.scale_num));
+		let scale_den = try!(BigDec::mul(left_unit.scale_den, right_unit.scale_den));
+		if dims == NO_DIMS {
+			return Ok(Quantity::plain(try!(mul_div(value, scale_num, scale_den))));
+		}
+		let name = try!(combined_name(&left_unit, &right_unit, '*'));
+		let unit = Unit { name, scale_num, scale_den, offset: BigDec::zero(), dims, kind: Kind::Plain };
+		Ok(Quantity { value, unit: Some(unit) })
+	}
+
+	pub fn div(left: Quantity, right: Quantity) -> Result<Quantity, Error> {
+		if left.is_date() || right.is_date() {
+			return Err(Quantity::incompatible(&left, &right));
+		}
+		let value = try!(BigDec::div(left.value, right.value));
+		let one = Unit {
+			name: Name::from_str("1").unwrap(),
+			scale_num: BigDec::from_i32(1),
+			scale_den: BigDec::from_i32(1),
+			offset: BigDec::zero(),
+			dims: NO_DIMS,
+			kind: Kind::Plain
+		};
+		let (left_unit, right_unit) = match (left.unit, right.unit) {
+			(unit, None) => return Ok(Quantity { value, unit }),
+			(None, Some(right_unit)) => (one, right_unit),
+			(Some(left_unit), Some(right_unit)) => (left_unit, right_unit)
+		};
+		let dims = Dims::combine(left_unit.dims, right_unit.dims, -1);
+		if dims == NO_DIMS {
+			// km/m is a plain number
+			let (num, den) = try!(scale_ratio(&left_unit, &right_unit));
+			return Ok(Quantity::plain(try!(mul_div(value, num, den))));
+		}
+		let (scale_num, scale_den) = try!(scale_ratio(&left_unit, &right_unit));
+		let name = try!(combined_name(&left_unit, &right_unit, '/'));
+		let unit = Unit { name, scale_num, scale_den, offset: BigDec::zero(), dims, kind: Kind::Plain };
+		Ok(Quantity { value, unit: Some(unit) })
+	}
+
+	// Same quantity expressed in the unit of target, as many times as
+	// the value of target. This is the x of "x in km".
+	pub fn convert(self, target: Quantity) -> Result<Quantity, Error> {
+		let (unit, target_unit) = match (self.unit, target.unit) {
+			(Some(unit), Some(target_unit)) if unit.dims == target_unit.dims && !self.is_date()
+				&& !target.is_date() => (unit, target_unit),
+			_ => return Err(Quantity::incompatible(&self, &target))
+		};
+		let value = if unit.offset.is_zero() && target_unit.offset.is_zero() {
+			let (num, den) = try!(scale_ratio(&unit, &target_unit));
+			try!(mul_div(self.value, num, den))
+		}
+		else {
+			// Through the base unit, for temperatures
+			let base = try!(BigDec::add(try!(mul_div(self.value, unit.scale_num, unit.scale_den)), unit.offset));
+			let from_zero = try!(BigDec::sub(base, target_unit.offset));
+			try!(mul_div(from_zero, target_unit.scale_den, target_unit.scale_num))
+		};
+		Ok(Quantity { value: try!(BigDec::div(value, target.value)), unit: Some(target_unit) })
+	}
+}
+
+#[test]
+fn test_find() {
+	assert!(find("km").is_some());
+	assert!(find("KiB").is_some());
+	assert!(find("Mbit").is_some());
+	assert!(find("min").is_some());
+	assert!(find("ms").is_some());
+	assert!(find("GHz").is_some());
+	assert!(find("Kim").is_none());
+	assert!(find("kdegC").is_none());
+	assert!(find("pi").is_none());
+	assert!(find("x").is_none());
+	assert_eq!("1048576", find("MiB").unwrap().scale_num.to_string());
+}
+
+#[test]
+fn test_magnitudes() {
+	assert_eq!("1000", magnitude("k").unwrap().to_string());
+	assert_eq!("0.000000000001", magnitude("p").unwrap().to_string());
+	assert_eq!("1073741824", magnitude("Gi").unwrap().to_string());
+	assert!(magnitude("km").is_none());
+	assert!(magnitude("K").is_none());
+
+	let best = |text: &str| match best_magnitude(parse(text)) {
+		Some((quotient, suffix)) => format!("{}{}", quotient.to_string(), suffix),
+		None => "none".to_string()
+	};
+	assert_eq!("64Ki", best("65536"));
+	assert_eq!("1.5M", best("1500000"));
+	assert_eq!("1M", best("1000000"));
+	assert_eq!("1.536k", best("1536"));
+	assert_eq!("250m", best("0.25"));
+	assert_eq!("2.5n", best("0.0000000025"));
+	assert_eq!("none", best("999.5"));
+	assert_eq!("none", best("0"));
+	assert_eq!("none", best("0.000000000000001"));
+}
+
+#[test]
+fn test_quantities() {
+	let quantity = |value: i32, unit: &str| Quantity { value: BigDec::from_i32(value), unit: find(unit) };
+	let (left, right, unit) = Quantity::common_values(quantity(5, "km"), quantity(300, "m")).ok().unwrap();
+	assert_eq!(("5", "0.3", "km"), (&left.to_string()[..], &right.to_string()[..], unit.unwrap().name()));
+	assert_eq!("incompatible units: km and s",
+		Quantity::common_values(quantity(5, "km"), quantity(3, "s")).err().unwrap().to_string());
+
+	let speed = Quantity::div(quantity(100, "km"), quantity(2, "h")).ok().unwrap();
+	assert_eq!("50 km/h", speed.to_string());
+	assert_eq!("25 m/s", Quantity::div(quantity(90, "km"), quantity(1, "h")).ok().unwrap()
+		.convert(Quantity::div(quantity(1, "m"), quantity(1, "s")).ok().unwrap()).ok().unwrap().to_string());
+	assert_eq!("3072 KiB", quantity(3, "MiB").convert(quantity(1, "KiB")).ok().unwrap().to_string());
+	assert_eq!("8", Quantity::div(quantity(1, "B"), quantity(1, "bit")).ok().unwrap().to_string());
+	assert_eq!("6 m^2", Quantity::mul(quantity(2, "m"), quantity(3, "m")).ok().unwrap().to_string());
+	let volume = Quantity::mul(Quantity::mul(quantity(3, "m"), quantity(2, "m")).ok().unwrap(), quantity(4, "m"))
+		.ok().unwrap();
+	assert_eq!("24 m^3", volume.to_string());
+	let area = Quantity::div(volume, quantity(2, "m")).ok().unwrap();
+	assert_eq!("12 m^2", area.to_string());
+	let accel = Quantity::div(Quantity::div(quantity(10, "m"), quantity(2, "s")).ok().unwrap(), quantity(5, "s"));
+	assert_eq!("1 m/s^2", accel.ok().unwrap().to_string());
+	let rate = Quantity::div(Quantity::div(quantity(6, "B"), quantity(1, "s")).ok().unwrap(), quantity(2, "m"));
+	assert_eq!("3 B/(s*m)", rate.ok().unwrap().to_string());
+	let distance = Quantity::mul(Quantity::div(quantity(50, "km"), quantity(1, "h")).ok().unwrap(), quantity(2, "h"));
+	assert_eq!("100 km", distance.ok().unwrap().to_string());
+	assert_eq!("32 degF", quantity(0, "degC").convert(quantity(1, "degF")).ok().unwrap().to_string());
+	assert_eq!("373.15 K", quantity(100, "degC").convert(quantity(1, "K")).ok().unwrap().to_string());
+	let compared = |left: Quantity, right: Quantity| {
+		let (left, right) = Quantity::compared_values(left, right).ok().unwrap();
+		(left.to_string(), right.to_string())
+	};
+	assert_eq!(("0".to_string(), "0".to_string()), compared(quantity(0, "degC"), quantity(32, "degF")));
+	assert_eq!(("0".to_string(), "-273.15".to_string()), compared(quantity(0, "degC"), quantity(0, "K")));
+	assert_eq!(("5".to_string(), "0.3".to_string()), compared(quantity(5, "km"), quantity(300, "m")));
+	assert_eq!("25 degC", Quantity::add(quantity(20, "degC"), quantity(9, "degF")).ok().unwrap().to_string());
+	assert_eq!("expected a number without unit, found s", quantity(3, "s").to_plain().err().unwrap().to_string());
+}
@@ -0,0 +1,93 @@
+use eval;
+use eval::Tree;
+use funcs::Registry;
+use settings::Settings;
+
+// Function defined by the user, such as f(x) = x*x + 1
+pub struct UserFunc {
+	pub name: String,
+	pub params: Vec<String>,
+	pub text: String, // the definition as typed
+	pub tree: Tree // the body, after the '='
+}
+
+impl UserFunc {
+	// Name and parameters, such as "f(x, y)"
+	pub fn signature(&self) -> String {
+		format!("{}({})", self.name, self.params.join(", "))
+	}
+}
+
+// Functions defined by the user, sorted by name
+pub struct UserFuncs {
+	funcs: Vec<UserFunc>
+}
+
+impl UserFuncs {
+	pub fn new() -> UserFuncs {
+		UserFuncs { funcs: Vec::new() }
+	}
+
+	pub fn funcs(&self) -> &[UserFunc] {
+		&self.funcs
+	}
+
+	pub fn get(&self, name: &str) -> Option<&UserFunc> {
+		self.funcs.iter().find(|func| func.name == name)
+	}
+
+	// Adds the function, or replaces the one with the same name
+	pub fn define(&mut self, func: UserFunc) {
+		match self.funcs.binary_search_by(|other| other.name.cmp(&func.name)) {
+			Ok(index) => self.funcs[index] = func,
+			Err(index) => self.funcs.insert(index, func)
+		}
+	}
+
+	pub fn remove(&mut self, name: &str) {
+		self.funcs.retain(|func| func.name != name);
+	}
+
+	// One definition per line
+	pub fn to_text(&self) -> String {
+		let mut ret = String::new();
+		for func in &self.funcs {
+			ret.push_str(&func.text);
+			ret.push('\n');
+		}
+		ret
+	}
+
+	// Reads functions written by to_text(). Lines that are not valid
+	// definitions are dropped, and so are the functions that the
+	// registry already has.
+	pub fn from_text(text: &str, settings: &Settings, registry: &Registry) -> UserFuncs {
+		let mut ret = UserFuncs::new();
+		for line in text.lines() {
+			if let Ok(Some(func)) = eval::parse_definition(line, settings, registry) {
+				ret.define(func);
+			}
+		}
+		ret
+	}
+}
+
+#[test]
+fn test_user_funcs() {
+	let settings = Settings::new();
+	let registry = Registry::new();
+	let text = "g(x) = x\nf(a, b) = a - b\n\nh = 3\nbad(x) = y\nsqrt(x) = x\n";
+	let mut user_funcs = UserFuncs::from_text(text, &settings, &registry);
+	assert_eq!(2, user_funcs.funcs().len());
+	assert_eq!("f(a, b)", user_funcs.funcs()[0].signature());
+	assert_eq!("g(x)", user_funcs.funcs()[1].signature());
+
+	let func = eval::parse_definition("g(y) = y * 2", &settings, &registry).ok().unwrap().unwrap();
+	user_funcs.define(func);
+	assert_eq!(2, user_funcs.funcs().len());
+	assert_eq!("f(a, b) = a - b\ng(y) = y * 2\n", user_funcs.to_text());
+
+	user_funcs.remove("f");
+	assert!(user_funcs.get("f").is_none());
+	assert!(user_funcs.get("g").is_some());
+}